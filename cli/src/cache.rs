@@ -0,0 +1,102 @@
+use anyhow::Context;
+use clap_derive::Subcommand;
+use serde_json::json;
+use tsticker::{
+    api_cache::ApiCache,
+    cache::{CacheEntry, StickerCache},
+};
+
+use crate::report;
+
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    /// Show where the sticker cache is and how big it is
+    Info,
+    /// Remove the least recently used stickers until the cache fits in --cache-max-size
+    Prune,
+    /// Remove every cached sticker and Bot API response
+    Clear,
+}
+
+/// Responses of the Bot API, kept next to the stickers when there is a cache directory.
+pub fn api_cache(cache: Option<&StickerCache>) -> ApiCache {
+    match cache {
+        Some(cache) => ApiCache::on_disk(cache.dir().join("api")),
+        None => ApiCache::new(),
+    }
+}
+
+fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / 1024.0 / 1024.0)
+}
+
+pub async fn cache_command(
+    cache: Option<StickerCache>,
+    command: &CacheCommand,
+) -> anyhow::Result<()> {
+    let cache = cache.context("no cache directory found, set --cache-dir")?;
+    let term = report::term();
+    let removed: Vec<CacheEntry> = match command {
+        CacheCommand::Info => {
+            let entries = cache.entries().await?;
+            let size: u64 = entries.iter().map(|entry| entry.size).sum();
+            let info = json!({
+                "dir": cache.dir(),
+                "stickers": entries.len(),
+                "size": size,
+                "max_size": cache.max_size(),
+            });
+            if !report::json(info) {
+                term.write_line(&format!("Cache: {}", cache.dir().display()))?;
+                term.write_line(&format!(
+                    "{} stickers, {} of {}",
+                    entries.len(),
+                    mib(size),
+                    mib(cache.max_size())
+                ))?;
+            }
+            return Ok(());
+        }
+        CacheCommand::Prune => cache.evict().await?,
+        CacheCommand::Clear => {
+            api_cache(Some(&cache)).clear().await?;
+            cache.clear().await?
+        }
+    };
+    let freed: u64 = removed.iter().map(|entry| entry.size).sum();
+    if !report::json(json!({ "removed": removed.len(), "freed": freed })) {
+        term.write_line(&format!(
+            "Removed {} stickers, freed {}",
+            removed.len(),
+            mib(freed)
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cache_command() {
+        assert!(cache_command(None, &CacheCommand::Info).await.is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = StickerCache::new(dir.path().to_owned());
+        let files = dir.path().join("files");
+        std::fs::create_dir_all(&files).unwrap();
+        std::fs::write(files.join("41674144"), b"sticker").unwrap();
+        cache_command(Some(cache.clone()), &CacheCommand::Info)
+            .await
+            .unwrap();
+        cache_command(Some(cache.clone()), &CacheCommand::Prune)
+            .await
+            .unwrap();
+        assert_eq!(cache.entries().await.unwrap().len(), 1);
+        cache_command(Some(cache.clone()), &CacheCommand::Clear)
+            .await
+            .unwrap();
+        assert!(cache.entries().await.unwrap().is_empty());
+    }
+}
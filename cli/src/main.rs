@@ -1,378 +1,1940 @@
+mod cache;
+mod report;
+mod upload;
 mod utils;
 use std::{
     collections::HashMap,
-    io::{Cursor, Write},
-    path::PathBuf,
-    time::{Instant, SystemTime, UNIX_EPOCH},
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use clap::{builder::Str, command, CommandFactory, Parser};
+use anyhow::Context;
+use clap::{CommandFactory, Parser};
 
-use clap_derive::Parser;
+use clap_complete::{generate, Shell};
+use clap_complete_nushell::Nushell;
+use clap_derive::{Parser, Subcommand, ValueEnum};
 use console::{Style, Term};
 use dialoguer::MultiSelect;
 
+use cache::{api_cache, cache_command, CacheCommand};
 use dotenv::dotenv;
-use futures::stream;
 use futures_util::StreamExt;
 use human_panic::setup_panic;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle, TermLike};
-use tsticker::bot::{Bot, Sticker, StickerSet, TelegramFile};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use report::{EventsFormat, Failure, ReportFormat, Stage};
+use reqwest::Url;
+use tsticker::{
+    api_cache::ApiCache,
+    bot::{Bot, Sticker, StickerFileExt, StickerSet, StickerType},
+    cache::{StickerCache, DEFAULT_MAX_SIZE},
+    convert::{convert_file, Color, ConvertOptions, OutputFormat},
+    download::{DownloadEvent, DownloadOptions, Downloader, DEFAULT_JOBS},
+    export::{export_set, ExportTarget},
+    link::StickerLink,
+    manifest::Manifest,
+    sanitize::{Sanitizer, SetFolder},
+    template::NameTemplate,
+};
+use upload::{clone_sticker_set, upload_sticker_set, CloneArgs, UploadArgs};
 
-static STYLE_PROGRESSBAR_LEN: &'static str = "[{elapsed_precise}] {bar} {pos:>7}/{len:7} {msg}";
+static STYLE_PROGRESSBAR_LEN: &str = "[{elapsed_precise}] {bar} {pos:>7}/{len:7} {msg}";
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
 pub struct Args {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// Options shared by every subcommand.
+#[derive(Debug, clap_derive::Args)]
+#[command(next_help_heading = "Global Options")]
+pub struct GlobalArgs {
     #[arg(
         short,
         long,
+        global = true,
         help = "Telegram bot token, or set TELEGRAM_BOT_TOKEN in environment variable"
     )]
     pub token: Option<String>,
-    #[arg(short, long, default_value=std::env::current_dir().unwrap().into_os_string())]
+
+    #[arg(short, long, global = true, default_value=std::env::current_dir().unwrap().into_os_string())]
     pub output: PathBuf,
-    #[arg(required = true, help = "Sticker links, get it by sharing button")]
+
+    #[arg(
+        long,
+        global = true,
+        help = "Base URL of Telegram Bot API server, or set TELEGRAM_API_URL in environment variable"
+    )]
+    pub api_url: Option<Url>,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Read files from local filesystem when the Bot API server runs with --local"
+    )]
+    pub local_mode: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = ReportFormat::Text,
+        help = "Print a JSON summary to stdout when finished, messages go to stderr"
+    )]
+    pub output_format: ReportFormat,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "Stream progress events to stdout, one JSON object per line"
+    )]
+    pub events: Option<EventsFormat>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Directory of the sticker cache shared by every sticker set, the user cache directory by default"
+    )]
+    pub cache_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "MIB",
+        default_value_t = DEFAULT_MAX_SIZE / 1024 / 1024,
+        help = "Shrink the sticker cache to this size after downloading"
+    )]
+    pub cache_max_size: u64,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Reuse sticker sets fetched in the last 5 minutes and file paths fetched in the last 50 minutes"
+    )]
+    pub cache_api: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Download sticker sets
+    Download(DownloadArgs),
+    /// Show sticker sets without downloading them
+    Info(InfoArgs),
+    /// List sticker sets downloaded into the output directory
+    List,
+    /// Download new stickers of every sticker set in the output directory
+    Sync(FetchArgs),
+    /// Convert sticker sets downloaded into the output directory
+    Convert(ConvertArgs),
+    /// Create a sticker set from a directory of stickers
+    Upload(UploadArgs),
+    /// Copy a sticker set into a new one owned by the bot
+    Clone(CloneArgs),
+    /// Inspect or prune the sticker cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+    /// Print shell completions
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
+}
+
+#[derive(Debug, clap_derive::Args)]
+pub struct DownloadArgs {
+    #[arg(
+        required = true,
+        help = "Sticker or emoji set links, get it by sharing button, or custom emoji ids"
+    )]
     pub links: Vec<String>,
 
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Only download sticker sets of these kinds: regular, mask, custom_emoji"
+    )]
+    pub kind: Vec<StickerType>,
+
+    #[arg(
+        short,
+        long,
+        visible_alias = "all",
+        default_value_t = false,
+        help = "Download every sticker set without asking, the default when no terminal is attached"
+    )]
+    pub yes: bool,
+
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        help = "Download sticker sets whose name or title matches, * and ? are wildcards, can be repeated"
+    )]
+    pub select: Vec<String>,
+
+    #[command(flatten)]
+    pub fetch: FetchArgs,
+}
+
+/// Options of `download` and `sync`.
+#[derive(Debug, clap_derive::Args)]
+pub struct FetchArgs {
     #[arg(short, long, default_value_t = false)]
     pub fast_failure: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Remove local stickers that were deleted from the sticker set"
+    )]
+    pub prune: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Write pack.csv with the metadata of every sticker along with pack.json"
+    )]
+    pub csv: bool,
+
+    #[arg(
+        long,
+        default_value_t = SetFolder::Title,
+        help = "Name sticker set directories after the title or the name of the set"
+    )]
+    pub folder: SetFolder,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Transliterate file and directory names to ASCII"
+    )]
+    pub ascii: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Check that downloaded files are valid webp, webm or tgs files"
+    )]
+    pub check_format: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Neither reuse nor store stickers in the sticker cache"
+    )]
+    pub no_cache: bool,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "File names of stickers, e.g. {index:03}_{emoji_name}_{unique_id}.{ext}, see the README for placeholders"
+    )]
+    pub name_template: Option<NameTemplate>,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = DEFAULT_JOBS,
+        help = "Number of stickers to fetch at the same time"
+    )]
+    pub jobs: usize,
+
+    #[arg(
+        long,
+        help = "Number of stickers of one sticker set to fetch at the same time"
+    )]
+    pub jobs_per_set: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Convert stickers to png, gif, apng, webp or mp4 after downloading"
+    )]
+    pub format: Option<OutputFormat>,
+
+    #[arg(
+        long,
+        requires = "format",
+        help = "Fill transparent background with colour, e.g. #ffffff"
+    )]
+    pub background: Option<Color>,
+
+    #[arg(
+        long,
+        requires = "format",
+        help = "Resize converted stickers to fit in a square of this size"
+    )]
+    pub size: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Export sticker sets for signal, whatsapp or discord, can be repeated"
+    )]
+    pub export: Vec<ExportTarget>,
 }
 
-async fn build_bot(token: String) -> Bot {
-    let mut term = Term::stdout();
+#[derive(Debug, clap_derive::Args)]
+pub struct InfoArgs {
+    #[arg(
+        required = true,
+        help = "Sticker or emoji set links, get it by sharing button, or custom emoji ids"
+    )]
+    pub links: Vec<String>,
+}
+
+#[derive(Debug, clap_derive::Args)]
+pub struct ConvertArgs {
+    #[arg(help = "Sticker set directories, every sticker set in the output directory by default")]
+    pub dirs: Vec<PathBuf>,
+
+    #[arg(long, help = "Convert stickers to png, gif, apng, webp or mp4")]
+    pub format: OutputFormat,
+
+    #[arg(long, help = "Fill transparent background with colour, e.g. #ffffff")]
+    pub background: Option<Color>,
+
+    #[arg(
+        long,
+        help = "Resize converted stickers to fit in a square of this size"
+    )]
+    pub size: Option<u32>,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = DEFAULT_JOBS,
+        help = "Number of stickers to convert at the same time"
+    )]
+    pub jobs: usize,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Elvish,
+    Fish,
+    Nushell,
+    Powershell,
+    Zsh,
+}
+
+async fn build_bot(
+    token: String,
+    api_url: Option<Url>,
+    local_mode: bool,
+    api_cache: Option<ApiCache>,
+) -> tsticker::error::Result<Bot> {
+    let mut term = report::term();
 
     term.write_line("[1/4] Login bot...").unwrap();
-    let bot = match Bot::login(token).await {
-        Ok(bot) => bot,
-        Err(e) => {
-            term.write_fmt(format_args!(
-                "{}: fail to login, {}\n",
-                Style::new().red().apply_to("Error"),
-                e.to_string()
-            ))
-            .unwrap();
-            std::process::exit(-1)
-        }
-    };
+    let mut builder = Bot::builder(token).local_mode(local_mode);
+    if let Some(api_url) = api_url {
+        builder = builder.api_url(api_url);
+    }
+    if let Some(api_cache) = api_cache {
+        builder = builder.api_cache(api_cache);
+    }
+    let bot = builder.login().await?;
     term.write_fmt(format_args!(
         "Hello, {}@{}\n",
         Style::new().green().apply_to(&bot.me().first_name),
         Style::new().blue().apply_to(&bot.me().username)
     ))
     .unwrap();
-    bot
+    Ok(bot)
 }
 
-async fn get_sticker_set(bot: &Bot, links: Vec<String>) -> Vec<StickerSet> {
-    let mut term = Term::stdout();
+/// Name of the sticker set collecting custom emoji requested by id.
+const CUSTOM_EMOJI_SET: &str = "custom_emoji";
+
+fn describe_error(err: &tsticker::error::Error) -> String {
+    if err.is_unauthorized() {
+        "bot token is rejected by Telegram, check --token or TELEGRAM_BOT_TOKEN".to_owned()
+    } else if let Some(retry_after) = err.retry_after() {
+        format!(
+            "too many requests, retry after {} seconds",
+            retry_after.as_secs()
+        )
+    } else {
+        err.to_string()
+    }
+}
+
+/// Parse every link, reporting all invalid ones at once.
+fn parse_links(links: Vec<String>) -> tsticker::error::Result<Vec<StickerLink>> {
+    let mut term = report::term();
+    let mut parsed = Vec::with_capacity(links.len());
+    let mut first_err = None;
+    for link in links {
+        match StickerLink::parse(&link) {
+            Ok(link) => parsed.push(link),
+            Err(err) => {
+                term.write_fmt(format_args!(
+                    "{}: {}\n",
+                    Style::new().red().apply_to("Error"),
+                    err
+                ))
+                .unwrap();
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    match first_err {
+        Some(err) => Err(err),
+        None => Ok(parsed),
+    }
+}
+
+async fn get_sticker_set(
+    bot: &Bot,
+    links: Vec<StickerLink>,
+) -> tsticker::error::Result<Vec<StickerSet>> {
+    let mut term = report::term();
 
     term.write_line("[2/4] Retrieve sticker set list...")
         .unwrap();
-    let links: Vec<String> = links
-        .into_iter()
-        .map(|x| {
-            if x.contains("/") {
-                x.split("/").last().unwrap().to_owned()
-            } else {
-                x
-            }
-        })
-        .collect();
+    let mut emoji_ids = Vec::new();
+    let mut set_names = Vec::new();
+    for link in links {
+        match link {
+            StickerLink::StickerSet(name) | StickerLink::EmojiSet(name) => set_names.push(name),
+            StickerLink::CustomEmoji(id) => emoji_ids.push(id),
+        }
+    }
 
     let mut sticker_set = Vec::new();
 
-    let prog = if let Ok(style) = ProgressStyle::with_template(&STYLE_PROGRESSBAR_LEN) {
-        ProgressBar::new(links.len() as u64).with_style(style)
+    let total = set_names.len() + usize::from(!emoji_ids.is_empty());
+    let prog = if let Ok(style) = ProgressStyle::with_template(STYLE_PROGRESSBAR_LEN) {
+        ProgressBar::new(total as u64).with_style(style)
     } else {
-        ProgressBar::new(links.len() as u64)
+        ProgressBar::new(total as u64)
     };
 
-    for name in links {
+    for name in set_names {
         prog.set_message(name.clone());
         let set = match bot.request_sticker_set(&name).await {
             Ok(e) => e,
+            Err(err) if err.is_not_found() => {
+                prog.suspend(|| {
+                    term.write_fmt(format_args!(
+                        "{}: sticker set {} does not exist, skipped\n",
+                        Style::new().yellow().apply_to("Warning"),
+                        name
+                    ))
+                    .unwrap()
+                });
+                prog.inc(1);
+                continue;
+            }
             Err(err) => {
+                prog.abandon();
                 term.write_fmt(format_args!(
-                    "{}: fail to retrieve sticker set of {}, {}",
+                    "{}: fail to retrieve sticker set of {}, {}\n",
                     Style::new().red().apply_to("Error"),
                     name,
-                    err.to_string()
+                    describe_error(&err)
                 ))
                 .unwrap();
-                std::process::exit(-1)
+                return Err(err);
             }
         };
         prog.inc(1);
         sticker_set.push(set);
     }
+
+    if !emoji_ids.is_empty() {
+        prog.set_message(format!("{} custom emoji", emoji_ids.len()));
+        let stickers = match bot.get_custom_emoji_stickers(&emoji_ids).await {
+            Ok(stickers) => stickers,
+            Err(err) => {
+                prog.abandon();
+                term.write_fmt(format_args!(
+                    "{}: fail to retrieve custom emoji, {}\n",
+                    Style::new().red().apply_to("Error"),
+                    describe_error(&err)
+                ))
+                .unwrap();
+                return Err(err);
+            }
+        };
+        if stickers.len() < emoji_ids.len() {
+            prog.suspend(|| {
+                term.write_fmt(format_args!(
+                    "{}: {} of {} custom emoji do not exist, skipped\n",
+                    Style::new().yellow().apply_to("Warning"),
+                    emoji_ids.len() - stickers.len(),
+                    emoji_ids.len()
+                ))
+                .unwrap()
+            });
+        }
+        if !stickers.is_empty() {
+            sticker_set.push(StickerSet {
+                name: CUSTOM_EMOJI_SET.to_owned(),
+                title: "Custom Emoji".to_owned(),
+                sticker_type: StickerType::CustomEmoji,
+                stickers,
+                thumbnail: None,
+            });
+        }
+        prog.inc(1);
+    }
     prog.finish_with_message("done");
 
-    sticker_set
+    Ok(sticker_set)
 }
 
-fn select_sticker_set(items: Vec<StickerSet>) -> Vec<StickerSet> {
-    let selection = MultiSelect::new()
-        .with_prompt("Please select sticker set you want to download:")
-        .items(&items)
-        .interact()
-        .unwrap();
-
-    let sticker_set: Vec<StickerSet> = items
+/// Keep sticker sets of `kinds`, or all of them if `kinds` is empty.
+fn filter_sticker_set(items: Vec<StickerSet>, kinds: &[StickerType]) -> Vec<StickerSet> {
+    if kinds.is_empty() {
+        return items;
+    }
+    let mut term = report::term();
+    items
         .into_iter()
-        .enumerate()
-        .filter(|(idx, _)| selection.contains(idx))
-        .map(|(_, sticker_set)| sticker_set)
-        .collect();
+        .filter(|set| {
+            let keep = kinds.contains(&set.sticker_type);
+            if !keep {
+                term.write_fmt(format_args!(
+                    "Skip {} sticker set {}\n",
+                    set.sticker_type, set.title
+                ))
+                .unwrap();
+            }
+            keep
+        })
+        .collect()
+}
 
-    Term::stdout()
-        .write_fmt(format_args!(
-            "Download sticker set: {}\n",
-            sticker_set
-                .iter()
-                .map(|s| s.to_string())
-                .collect::<Vec<String>>()
-                .join(", ")
-        ))
-        .unwrap();
-    sticker_set
+/// Whether `text` matches the glob `pattern`, where `*` matches any characters and `?`
+/// a single one, ignoring case.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
-fn convert_sticker_to_filename(sticker: &Sticker) -> String {
-    let emoji_name = emojis::get(&sticker.emoji)
-        .map(|e| e.name())
-        .unwrap_or("emoji_missing")
-        .to_string();
+/// How sticker sets to download are picked.
+#[derive(Debug, Clone, PartialEq)]
+enum Selection {
+    /// Ask with a prompt.
+    Prompt,
+    All,
+    /// Sets whose name or title matches any of the patterns.
+    Patterns(Vec<String>),
+}
+
+impl Selection {
+    /// Never prompts when no terminal is attached, so scripts don't hang.
+    fn new(all: bool, patterns: Vec<String>, interactive: bool) -> Self {
+        if !patterns.is_empty() {
+            Selection::Patterns(patterns)
+        } else if all || !interactive {
+            Selection::All
+        } else {
+            Selection::Prompt
+        }
+    }
+}
 
-    let file_id = &sticker.file_id;
+fn select_sticker_set(items: Vec<StickerSet>, selection: &Selection) -> Vec<StickerSet> {
+    let mut term = report::term();
+    let sticker_set: Vec<StickerSet> = match selection {
+        Selection::All => items,
+        Selection::Patterns(patterns) => items
+            .into_iter()
+            .filter(|set| {
+                patterns.iter().any(|pattern| {
+                    matches_pattern(pattern, &set.name) || matches_pattern(pattern, &set.title)
+                })
+            })
+            .collect(),
+        Selection::Prompt => {
+            let selected = match MultiSelect::new()
+                .with_prompt("Please select sticker set you want to download:")
+                .items(&items)
+                .interact()
+            {
+                Ok(selected) => selected,
+                Err(e) => {
+                    term.write_fmt(format_args!(
+                        "{}: fail to select sticker set, {}\n",
+                        Style::new().red().apply_to("Error"),
+                        e
+                    ))
+                    .unwrap();
+                    Vec::new()
+                }
+            };
+            items
+                .into_iter()
+                .enumerate()
+                .filter(|(idx, _)| selected.contains(idx))
+                .map(|(_, sticker_set)| sticker_set)
+                .collect()
+        }
+    };
 
-    let ext = sticker.file_ext().to_string();
+    term.write_fmt(format_args!(
+        "Download sticker set: {}\n",
+        sticker_set
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    ))
+    .unwrap();
+    sticker_set
+}
 
-    format!("{}_{}.{}", emoji_name, file_id, ext)
+/// Sticker files on disk and the failures after downloading.
+#[derive(Debug, Default)]
+struct Downloaded {
+    /// Set name, sticker and where it is stored.
+    files: Vec<(String, Sticker, PathBuf)>,
+    /// Files downloaded by this run, rather than kept from an earlier one.
+    written: Vec<PathBuf>,
+    failures: Vec<Failure>,
 }
 
 async fn download_sticker_set(
     bot: &Bot,
     sticker_sets: Vec<StickerSet>,
-    dest_dir: PathBuf,
-    parallel_number: Option<usize>,
+    options: DownloadOptions,
     fast_failure: bool,
-) {
-    let term = Term::stdout();
+) -> Downloaded {
+    let term = report::term();
 
     term.write_line("[3/4] Downloading sticker...").unwrap();
 
     let mp = MultiProgress::new();
-    let sty = ProgressStyle::with_template(&STYLE_PROGRESSBAR_LEN)
-        .unwrap_or(ProgressStyle::default_bar());
+    let sty =
+        ProgressStyle::with_template(STYLE_PROGRESSBAR_LEN).unwrap_or(ProgressStyle::default_bar());
 
-    let total_num_sticker = sticker_sets.iter().map(|s| s.stickers.len() as u64).sum();
-
-    // Progressbar of sticker to get item url
-    let pb_fetch_url_sticker = mp
-        .add(ProgressBar::new(total_num_sticker))
+    let pb_sticker_set = mp
+        .add(ProgressBar::new(sticker_sets.len() as u64))
         .with_style(sty.clone());
+    let pb_sticker = mp.add(ProgressBar::new(0)).with_style(sty);
 
-    // Get file id of each sticker
+    let report = |message: String| {
+        mp.suspend(|| term.write_line(&message).unwrap());
+    };
 
-    let mp_ref = &mp;
-    let term_ref = &term;
-    let dest_dir_ref = &dest_dir;
+    let mut events = Downloader::new(bot.clone(), sticker_sets, options).run();
+    let mut downloaded = Downloaded::default();
+    while let Some(event) = events.next().await {
+        report::emit_download(&event);
+        match event {
+            DownloadEvent::SetStarted { title, total, .. } => {
+                pb_sticker_set.set_message(format!("Downloading stickers {}", title));
+                pb_sticker.inc_length(total as u64);
+            }
+            DownloadEvent::StickerSkipped { set, sticker, path } => {
+                pb_sticker.inc(1);
+                downloaded.files.push((set, *sticker, path));
+            }
+            DownloadEvent::StickerDownloaded {
+                set, sticker, path, ..
+            } => {
+                pb_sticker.inc(1);
+                pb_sticker.set_message(format!(
+                    "Downloading sticker {}",
+                    sticker.emoji.as_deref().unwrap_or_default()
+                ));
+                downloaded.written.push(path.clone());
+                downloaded.files.push((set, *sticker, path));
+            }
+            DownloadEvent::StickerFailed {
+                set,
+                sticker,
+                error,
+            } => {
+                pb_sticker.inc(1);
+                report(format!(
+                    "{}: fail to get sticker {}({}), {}",
+                    Style::new().red().apply_to("Error"),
+                    sticker.emoji.as_deref().unwrap_or_default(),
+                    sticker.file_id,
+                    error
+                ));
+                downloaded.failures.push(Failure {
+                    stage: Stage::Download,
+                    set: Some(set),
+                    sticker: Some(sticker.file_unique_id),
+                    reason: describe_error(&error),
+                });
+                if fast_failure {
+                    break;
+                }
+            }
+            DownloadEvent::StickersPruned { set, paths } => report(format!(
+                "Removed {} stickers deleted from {}",
+                paths.len(),
+                set
+            )),
+            DownloadEvent::SetFailed { set, error } => {
+                pb_sticker_set.inc(1);
+                report(format!(
+                    "{}: fail to download sticker set {}, {}",
+                    Style::new().red().apply_to("Error"),
+                    set,
+                    error
+                ));
+                downloaded.failures.push(Failure {
+                    stage: Stage::Download,
+                    set: Some(set),
+                    sticker: None,
+                    reason: describe_error(&error),
+                });
+                if fast_failure {
+                    break;
+                }
+            }
+            DownloadEvent::SetFinished { .. } => pb_sticker_set.inc(1),
+        }
+    }
+    pb_sticker.finish();
+    pb_sticker_set.finish_with_message("done");
 
-    let pb_sticker_set = mp
-        .add(ProgressBar::new(sticker_sets.len() as u64))
-        .with_style(sty.clone());
+    downloaded
+}
 
-    let pb_sticker = mp.add(ProgressBar::new(0)).with_style(sty.clone());
-
-    let pb_sticker_set_ref = &pb_sticker_set;
-    let pb_sticker_ref = &pb_sticker;
-    stream::iter(sticker_sets)
-        .enumerate()
-        .then(|(idx, sticker_set)| async move {
-            let StickerSet {
-                title, stickers, ..
-            } = sticker_set;
-            pb_sticker_set_ref.set_message(format!("Parsing sticker items of {}", title));
-            pb_sticker_set_ref.set_position(idx as u64 +1);
-
-            let stickers = stream::iter(stickers)
-                .then(|s| async {
-                    let id = bot.request_file_id(&s).await;
-                    (s, id)
-                })
-                .filter_map(|(s, file_id_res)| async move {
-                    let mut term = term_ref.clone();
-                    match file_id_res {
-                        Ok(v) => Some((s, v)),
-                        Err(e) => {
-                            term.write_fmt(format_args!(
-                                "{}: fail to get file url of sticker {}({}), {}",
-                                Style::new().red().apply_to("Error"),
-                                s.emoji,
-                                s.file_id,
-                                e.to_string()
-                            ))
-                            .unwrap();
-                            if fast_failure {
-                                panic!("fail to get file url of sticker");
-                            }
-                            None
-                        }
-                    }
-                })
-                .collect::<Vec<_>>()
-                .await;
+/// Whether `dst` was converted from the current content of `src` already.
+async fn is_converted(src: &Path, dst: &Path) -> bool {
+    let modified = |path| async move { tokio::fs::metadata(path).await?.modified() };
+    match (modified(src).await, modified(dst).await) {
+        (Ok(src), Ok(dst)) => dst >= src,
+        _ => false,
+    }
+}
+
+/// Convert `files`, returning the files written and the failures.
+async fn convert_stickers(
+    files: Vec<(PathBuf, StickerFileExt)>,
+    options: ConvertOptions,
+    jobs: usize,
+) -> (Vec<PathBuf>, Vec<Failure>) {
+    let term = report::term();
 
-            (idx, (title, stickers))
+    term.write_line("[4/4] Converting stickers...").unwrap();
+
+    let prog = if let Ok(style) = ProgressStyle::with_template(STYLE_PROGRESSBAR_LEN) {
+        ProgressBar::new(files.len() as u64).with_style(style)
+    } else {
+        ProgressBar::new(files.len() as u64)
+    };
+    prog.set_message(format!("Converting to {}", options.format));
+
+    let options = &options;
+    let prog = &prog;
+    let term = &term;
+    let results: Vec<Result<Option<PathBuf>, Failure>> = futures_util::stream::iter(files)
+        .map(|(src, ext)| async move {
+            let dst = options.output_path(&src, ext);
+            if is_converted(&src, &dst).await {
+                prog.inc(1);
+                return Ok(None);
+            }
+            let res = convert_file(&src, ext, options).await;
+            prog.inc(1);
+            match res {
+                Ok(dst) => {
+                    report::emit(
+                        "sticker_converted",
+                        serde_json::json!({ "path": src, "output": dst }),
+                    );
+                    Ok(Some(dst))
+                }
+                Err(err) => {
+                    prog.suspend(|| {
+                        term.write_line(&format!(
+                            "{}: fail to convert {}, {}",
+                            Style::new().red().apply_to("Error"),
+                            src.display(),
+                            err
+                        ))
+                        .unwrap()
+                    });
+                    report::emit(
+                        "convert_failed",
+                        serde_json::json!({ "path": src, "error": err.to_string() }),
+                    );
+                    Err(Failure {
+                        stage: Stage::Convert,
+                        set: None,
+                        sticker: Some(src.display().to_string()),
+                        reason: err.to_string(),
+                    })
+                }
+            }
         })
-        .for_each(|(idx, (sticker_title, stickers))| async move {
-            let title_ref = sticker_title.as_str();
-            pb_sticker_set_ref.set_message(format!("Downloading stickers {}", sticker_title));
-            pb_sticker_set_ref.set_position(idx as u64 +1);
+        .buffer_unordered(jobs.max(1))
+        .collect()
+        .await;
+    prog.finish_with_message("done");
 
-            pb_sticker_ref.set_position(0);
-            pb_sticker_ref.set_length(stickers.len() as u64);
-            pb_sticker_ref.set_message(format!("Preparing stickers {}", sticker_title));
+    let mut written = Vec::new();
+    let mut failures = Vec::new();
+    for res in results {
+        match res {
+            Ok(dst) => written.extend(dst),
+            Err(failure) => failures.push(failure),
+        }
+    }
+    (written, failures)
+}
 
-            stream::iter(stickers)
-                .then(|(sticker, file_id_res)| async move {
+async fn export_sticker_sets(
+    sticker_sets: &[StickerSet],
+    files: &[(String, Sticker, PathBuf)],
+    targets: &[ExportTarget],
+    options: &DownloadOptions,
+) -> Vec<Failure> {
+    let mut term = report::term();
 
-                    pb_sticker_ref.inc(1);
-                    pb_sticker_ref.set_message(format!("Downloading sticker {}", sticker.emoji));
-                    (sticker, bot.download_file(&file_id_res).await)
-                })
-                .filter_map(|(sticker, resp_res)| async move {
-                    let mut term = term_ref.clone();
-                    match resp_res {
-                        Ok(v) => Some((sticker, v)),
-                        Err(e) => {
-                            term.write_fmt(format_args!(
-                                "{}: fail to get sticker {}({}), {}",
-                                Style::new().red().apply_to("Error"),
-                                sticker.emoji,
-                                sticker.file_id,
-                                e.to_string()
-                            ))
-                            .unwrap();
-                            if fast_failure {
-                                panic!("fail to get sticker");
-                            }
-                            None
-                        }
+    term.write_line("[4/4] Exporting sticker sets...").unwrap();
+
+    let mut failures = Vec::new();
+    for set in sticker_sets {
+        let mut stickers: Vec<(Sticker, PathBuf)> = files
+            .iter()
+            .filter(|(name, _, _)| name == &set.name)
+            .map(|(_, sticker, path)| (sticker.clone(), path.clone()))
+            .collect();
+        stickers.sort_by_key(|(sticker, _)| {
+            set.stickers
+                .iter()
+                .position(|s| s.file_unique_id == sticker.file_unique_id)
+        });
+        let set_dir = options.set_dir(set);
+        let set_dir_name = set_dir.file_name().unwrap_or_default();
+        for target in targets {
+            let dir = options
+                .destination
+                .join("export")
+                .join(target.to_string())
+                .join(set_dir_name);
+            match export_set(*target, set, &stickers, &dir).await {
+                Ok(report) => {
+                    for src in report.left_out.iter() {
+                        let reason = format!("too big for {} even when shrunk", target);
+                        term.write_fmt(format_args!(
+                            "{}: {} left out of {}, {}\n",
+                            Style::new().red().apply_to("Error"),
+                            src.display(),
+                            set.title,
+                            reason
+                        ))
+                        .unwrap();
+                        failures.push(Failure {
+                            stage: Stage::Export,
+                            set: Some(set.name.clone()),
+                            sticker: Some(src.display().to_string()),
+                            reason,
+                        });
                     }
-                })
-                .filter_map(|(sticker, resp)| async move {
-                    let mut term = term_ref.clone();
-                    let file_path = convert_sticker_to_filename(&sticker);
-                    let mut dst = dest_dir_ref.clone();
-                    dst.push(title_ref.clone());
-                    dst.push(file_path);
-
-                    // Create destination directory if not exists
-                    if let Some(parent) = dst.parent() {
-                        if !parent.exists() {
-                            let res = tokio::fs::create_dir_all(&parent).await;
-                            if let Err(err) = res {
-                                term.write_fmt(format_args!(
-                                    "{}: {}",
-                                    Style::new().red().apply_to("Error"),
-                                    err.to_string()
-                                ))
-                                .unwrap();
-                                if fast_failure {
-                                    panic!("fail to create destination directory");
-                                } else {
-                                    return None;
-                                }
-                            }
-                        }
+                    for warning in report.warnings {
+                        term.write_fmt(format_args!(
+                            "{}: {}, {}\n",
+                            Style::new().yellow().apply_to("Warning"),
+                            set.title,
+                            warning
+                        ))
+                        .unwrap();
                     }
+                    term.write_fmt(format_args!(
+                        "Exported {} stickers of {} to {}\n",
+                        report.exported,
+                        set.title,
+                        report.dir.display()
+                    ))
+                    .unwrap();
+                    report::emit(
+                        "set_exported",
+                        serde_json::json!({
+                            "set": set.name,
+                            "target": target.to_string(),
+                            "dir": report.dir,
+                            "exported": report.exported,
+                            "left_out": report.left_out,
+                        }),
+                    );
+                }
+                Err(err) => {
+                    term.write_fmt(format_args!(
+                        "{}: fail to export {} for {}, {}\n",
+                        Style::new().red().apply_to("Error"),
+                        set.title,
+                        target,
+                        err
+                    ))
+                    .unwrap();
+                    failures.push(Failure {
+                        stage: Stage::Export,
+                        set: Some(set.name.clone()),
+                        sticker: None,
+                        reason: format!("{}: {}", target, err),
+                    });
+                }
+            }
+        }
+    }
 
-                    let file = match tokio::fs::File::create(&dst).await {
-                        Ok(file) => file,
-                        Err(err) => {
-                            term.write_fmt(format_args!(
-                                "{}: fail to create local file {},  {}",
-                                dst.as_path().to_string_lossy(),
-                                Style::new().red().apply_to("Error"),
-                                err.to_string()
-                            ))
-                            .unwrap();
-
-                            if fast_failure {
-                                panic!("fail to create local file");
-                            } else {
-                                return None;
-                            }
-                        }
-                    };
-
-                    Some((dst, file, resp))
-                })
-                .for_each_concurrent(parallel_number, |(dst, mut file, resp)| async move {
-                    let mut bytes = resp.bytes_stream();
-                    let mut term = term_ref.clone();
-                    while let Some(item) = bytes.next().await {
-                        match item {
-                            Ok(data) => {
-                                if let Err(err) =
-                                    tokio::io::copy(&mut data.as_ref(), &mut file).await
-                                {
-                                    term.write_fmt(format_args!(
-                                        "{}: fail to write to file {}, {}",
-                                        dst.as_path().to_string_lossy(),
-                                        Style::new().red().apply_to("Error"),
-                                        err.to_string()
-                                    ))
-                                    .unwrap();
-                                    if fast_failure {
-                                        std::process::exit(-1);
-                                    }
-                                };
-                            }
-                            Err(e) => {
-                                term.write_fmt(format_args!(
-                                    "{}: fail to download file {}, {}",
-                                    dst.as_path().to_string_lossy(),
-                                    Style::new().red().apply_to("Error"),
-                                    e.to_string()
-                                ))
-                                .unwrap();
-                                if fast_failure {
-                                    panic!("fail to fetch bytes")
-                                }
-                            }
-                        }
-                    }
-                })
-                .await;
-        })
-        .await;
+    failures
+}
+
+/// Sticker sets downloaded into `output`, found by their manifest.
+async fn local_sticker_sets(output: &Path) -> Vec<(PathBuf, Manifest)> {
+    let Ok(entries) = std::fs::read_dir(output) else {
+        return Vec::new();
+    };
+    let mut sets = Vec::new();
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        if !Manifest::path(&dir).is_file() {
+            continue;
+        }
+        if let Ok(manifest) = Manifest::load(&dir).await {
+            if !manifest.name.is_empty() {
+                sets.push((dir, manifest));
+            }
+        }
+    }
+    sets.sort_by(|(a, _), (b, _)| a.cmp(b));
+    sets
+}
+
+fn list_sticker_sets(sets: &[(PathBuf, Manifest)]) {
+    let mut term = report::term();
+    for (dir, manifest) in sets {
+        term.write_fmt(format_args!(
+            "{} ({}), {} stickers in {}\n",
+            Style::new().bold().apply_to(&manifest.title),
+            manifest.name,
+            manifest.stickers.len(),
+            dir.display()
+        ))
+        .unwrap();
+    }
+}
+
+fn show_sticker_sets(sets: &[StickerSet]) {
+    let mut term = report::term();
+    for set in sets {
+        let count = |ext| set.stickers.iter().filter(|s| s.file_ext() == ext).count();
+        term.write_fmt(format_args!(
+            "{}\n  name: {}\n  kind: {}\n  stickers: {} ({} static, {} animated, {} video)\n",
+            Style::new().bold().apply_to(&set.title),
+            set.name,
+            set.sticker_type,
+            set.stickers.len(),
+            count(StickerFileExt::Webp),
+            count(StickerFileExt::Tgs),
+            count(StickerFileExt::Webm)
+        ))
+        .unwrap();
+    }
+}
+
+/// Links to fetch local sticker sets again. Custom emoji requested by id are left
+/// out, since their ids are not recorded.
+fn sync_links(sets: &[(PathBuf, Manifest)]) -> Vec<StickerLink> {
+    sets.iter()
+        .filter(|(_, manifest)| manifest.name != CUSTOM_EMOJI_SET)
+        .map(|(_, manifest)| StickerLink::StickerSet(manifest.name.clone()))
+        .collect()
+}
+
+/// Directories of local sticker sets by set name, so that sync writes each set back
+/// where it was found.
+fn sync_dirs(sets: &[(PathBuf, Manifest)]) -> HashMap<String, PathBuf> {
+    sets.iter()
+        .map(|(dir, manifest)| (manifest.name.clone(), dir.clone()))
+        .collect()
+}
+
+/// Downloaded stickers of the sticker sets in `dirs`, as recorded in their manifest.
+async fn recorded_stickers(dirs: &[PathBuf]) -> Vec<(PathBuf, StickerFileExt)> {
+    let mut files = Vec::new();
+    for dir in dirs {
+        let Ok(manifest) = Manifest::load(dir).await else {
+            continue;
+        };
+        for entry in manifest.stickers.into_values() {
+            let path = dir.join(entry.file_name);
+            let ext = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("webp") => StickerFileExt::Webp,
+                Some("webm") => StickerFileExt::Webm,
+                Some("tgs") => StickerFileExt::Tgs,
+                _ => continue,
+            };
+            files.push((path, ext));
+        }
+    }
+    files
+}
+
+fn print_completions(shell: CompletionShell) {
+    let mut command = Args::command();
+    let name = command.get_name().to_owned();
+    let out = &mut std::io::stdout();
+    match shell {
+        CompletionShell::Bash => generate(Shell::Bash, &mut command, name, out),
+        CompletionShell::Elvish => generate(Shell::Elvish, &mut command, name, out),
+        CompletionShell::Fish => generate(Shell::Fish, &mut command, name, out),
+        CompletionShell::Nushell => generate(Nushell, &mut command, name, out),
+        CompletionShell::Powershell => generate(Shell::PowerShell, &mut command, name, out),
+        CompletionShell::Zsh => generate(Shell::Zsh, &mut command, name, out),
+    }
+}
+
+/// Log in with the token of `global`, printing why it failed.
+async fn login(global: &GlobalArgs) -> anyhow::Result<Bot> {
+    let res = async {
+        let token = match global.token.clone() {
+            Some(token) => token,
+            None => std::env::var("TELEGRAM_BOT_TOKEN")
+                .context("token is not set, use --token or TELEGRAM_BOT_TOKEN")?,
+        };
+        let api_url = match global.api_url.clone() {
+            Some(api_url) => Some(api_url),
+            None => match std::env::var("TELEGRAM_API_URL") {
+                Ok(url) => Some(Url::parse(&url).context("TELEGRAM_API_URL is not a valid url")?),
+                Err(_) => None,
+            },
+        };
+        let api_cache = global
+            .cache_api
+            .then(|| api_cache(open_cache(global).as_ref()));
+        build_bot(token, api_url, global.local_mode, api_cache)
+            .await
+            .map_err(|e| anyhow::anyhow!("fail to login, {}", describe_error(&e)))
+    }
+    .await;
+    if let Err(e) = &res {
+        print_error(e);
+    }
+    res
+}
+
+fn report_created(created: anyhow::Result<StickerLink>) -> ExitCode {
+    match created {
+        Ok(link) => {
+            if !report::json(serde_json::json!({ "link": link.to_string() })) {
+                report::term()
+                    .write_line(&format!("Created {}", link))
+                    .unwrap();
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => report_error(e),
+    }
+}
+
+fn print_error(e: &anyhow::Error) {
+    report::term()
+        .write_fmt(format_args!(
+            "{}: {:#}\n",
+            Style::new().red().apply_to("Error"),
+            e
+        ))
+        .unwrap();
+}
+
+fn report_error(e: anyhow::Error) -> ExitCode {
+    print_error(&e);
+    report_reason(format!("{:#}", e))
+}
+
+/// The JSON failure of commands other than `download` and `sync`, once the reason
+/// was printed.
+fn report_reason(reason: String) -> ExitCode {
+    report::json(serde_json::json!({ "error": reason }));
+    ExitCode::FAILURE
+}
+
+/// The sticker cache, unless no cache directory can be found.
+fn open_cache(global: &GlobalArgs) -> Option<StickerCache> {
+    let dir = global
+        .cache_dir
+        .clone()
+        .or_else(StickerCache::default_dir)?;
+    Some(StickerCache::new(dir).with_max_size(global.cache_max_size * 1024 * 1024))
+}
+
+/// Report an error stopping `download` or `sync` before any sticker is fetched.
+/// The JSON summary of `download` and `sync` failing before any sticker set, once the
+/// reason was printed.
+fn report_failed(reason: String) -> ExitCode {
+    let failure = Failure {
+        stage: Stage::Download,
+        set: None,
+        sticker: None,
+        reason,
+    };
+    report::summary(&[], &[], &[failure]);
+    ExitCode::FAILURE
+}
+
+/// Download `sticker_set`, then convert and export it as `args` asks.
+async fn fetch_sticker_sets(
+    bot: &Bot,
+    sticker_set: Vec<StickerSet>,
+    args: FetchArgs,
+    output: &Path,
+    set_dirs: HashMap<String, PathBuf>,
+    cache: Option<StickerCache>,
+) -> ExitCode {
+    let FetchArgs {
+        fast_failure,
+        prune,
+        csv,
+        folder,
+        ascii,
+        check_format,
+        no_cache,
+        name_template,
+        jobs,
+        jobs_per_set,
+        format,
+        background,
+        size,
+        export,
+    } = args;
+    let options = DownloadOptions {
+        jobs,
+        jobs_per_set,
+        prune,
+        pack_csv: csv,
+        check_format,
+        cache: cache.filter(|_| !no_cache),
+        name_template,
+        folder,
+        sanitizer: Sanitizer {
+            ascii,
+            ..Sanitizer::default()
+        },
+        // sets synced in place keep the names they were downloaded with
+        recorded_naming: !set_dirs.is_empty(),
+        set_dirs,
+        ..DownloadOptions::new(output.to_owned())
+    };
+    let Downloaded {
+        files,
+        mut written,
+        mut failures,
+    } = download_sticker_set(bot, sticker_set.clone(), options.clone(), fast_failure).await;
+    if let Some(format) = format {
+        let options = ConvertOptions {
+            background,
+            size,
+            ..ConvertOptions::new(format)
+        };
+        let converting = files
+            .iter()
+            .map(|(_, sticker, path)| (path.clone(), sticker.file_ext()))
+            .collect();
+        let (converted, failed) = convert_stickers(converting, options, jobs).await;
+        written.extend(converted);
+        failures.extend(failed);
+    }
+    if !export.is_empty() {
+        failures.extend(export_sticker_sets(&sticker_set, &files, &export, &options).await);
+    }
+    report::summary(&sticker_set, &written, &failures);
+    if !failures.is_empty() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     setup_panic!();
-    let Args {
-        token,
-        output,
-        links,
-        fast_failure,
-    } = Args::parse();
+    let Args { global, command } = Args::parse();
     dotenv().ok();
+    report::init(global.output_format, global.events);
+    let output = global.output.clone();
 
-    let token = if let Some(token) = token {
-        token
-    } else {
-        if let Ok(env) = std::env::var("TELEGRAM_BOT_TOKEN") {
-            env
-        } else {
-            panic!("Token is not set");
+    match command {
+        Command::Download(args) => {
+            let links = match parse_links(args.links) {
+                Ok(links) => links,
+                Err(err) => return report_failed(describe_error(&err)),
+            };
+            let bot = match login(&global).await {
+                Ok(bot) => bot,
+                Err(e) => return report_failed(format!("{:#}", e)),
+            };
+            let sticker_set = match get_sticker_set(&bot, links).await {
+                Ok(sticker_set) => sticker_set,
+                Err(err) => return report_failed(describe_error(&err)),
+            };
+            let interactive = std::io::stdin().is_terminal() && Term::stderr().is_term();
+            let selection = Selection::new(args.yes, args.select, interactive);
+            let sticker_set =
+                select_sticker_set(filter_sticker_set(sticker_set, &args.kind), &selection);
+            fetch_sticker_sets(
+                &bot,
+                sticker_set,
+                args.fetch,
+                &output,
+                HashMap::new(),
+                open_cache(&global),
+            )
+            .await
         }
+        Command::Info(args) => {
+            let links = match parse_links(args.links) {
+                Ok(links) => links,
+                Err(err) => return report_reason(describe_error(&err)),
+            };
+            let bot = match login(&global).await {
+                Ok(bot) => bot,
+                Err(e) => return report_reason(format!("{:#}", e)),
+            };
+            let sticker_set = match get_sticker_set(&bot, links).await {
+                Ok(sticker_set) => sticker_set,
+                Err(err) => return report_reason(describe_error(&err)),
+            };
+            let sets: Vec<serde_json::Value> = sticker_set
+                .iter()
+                .map(|set| {
+                    let count = |ext| set.stickers.iter().filter(|s| s.file_ext() == ext).count();
+                    serde_json::json!({
+                        "name": set.name,
+                        "title": set.title,
+                        "kind": set.sticker_type.as_str(),
+                        "stickers": set.stickers.len(),
+                        "static": count(StickerFileExt::Webp),
+                        "animated": count(StickerFileExt::Tgs),
+                        "video": count(StickerFileExt::Webm),
+                    })
+                })
+                .collect();
+            if !report::json(serde_json::json!({ "sets": sets })) {
+                show_sticker_sets(&sticker_set);
+            }
+            ExitCode::SUCCESS
+        }
+        Command::List => {
+            let sets = local_sticker_sets(&output).await;
+            let listed: Vec<serde_json::Value> = sets
+                .iter()
+                .map(|(dir, manifest)| {
+                    serde_json::json!({
+                        "name": manifest.name,
+                        "title": manifest.title,
+                        "stickers": manifest.stickers.len(),
+                        "dir": dir,
+                    })
+                })
+                .collect();
+            if report::json(serde_json::json!({ "sets": listed })) {
+                return ExitCode::SUCCESS;
+            }
+            if sets.is_empty() {
+                report::term()
+                    .write_line(&format!("No sticker set in {}", output.display()))
+                    .unwrap();
+            }
+            list_sticker_sets(&sets);
+            ExitCode::SUCCESS
+        }
+        Command::Sync(args) => {
+            let sets = local_sticker_sets(&output).await;
+            let links = sync_links(&sets);
+            if links.is_empty() {
+                report::term()
+                    .write_line(&format!("No sticker set to sync in {}", output.display()))
+                    .unwrap();
+                report::summary(&[], &[], &[]);
+                return ExitCode::SUCCESS;
+            }
+            let bot = match login(&global).await {
+                Ok(bot) => bot,
+                Err(e) => return report_failed(format!("{:#}", e)),
+            };
+            let sticker_set = match get_sticker_set(&bot, links).await {
+                Ok(sticker_set) => sticker_set,
+                Err(err) => return report_failed(describe_error(&err)),
+            };
+            fetch_sticker_sets(
+                &bot,
+                sticker_set,
+                args,
+                &output,
+                sync_dirs(&sets),
+                open_cache(&global),
+            )
+            .await
+        }
+        Command::Convert(args) => {
+            let dirs = if args.dirs.is_empty() {
+                local_sticker_sets(&output)
+                    .await
+                    .into_iter()
+                    .map(|(dir, _)| dir)
+                    .collect()
+            } else {
+                args.dirs
+            };
+            let options = ConvertOptions {
+                background: args.background,
+                size: args.size,
+                ..ConvertOptions::new(args.format)
+            };
+            let files = recorded_stickers(&dirs).await;
+            let (written, failures) = convert_stickers(files, options, args.jobs).await;
+            report::summary(&[], &written, &failures);
+            if !failures.is_empty() {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Command::Upload(args) => {
+            let bot = match login(&global).await {
+                Ok(bot) => bot,
+                Err(e) => return report_reason(format!("{:#}", e)),
+            };
+            report_created(upload_sticker_set(&bot, &args).await)
+        }
+        Command::Clone(args) => {
+            let bot = match login(&global).await {
+                Ok(bot) => bot,
+                Err(e) => return report_reason(format!("{:#}", e)),
+            };
+            report_created(clone_sticker_set(&bot, &args).await)
+        }
+        Command::Cache { action } => match cache_command(open_cache(&global), &action).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => report_error(e),
+        },
+        Command::Completions { shell } => {
+            print_completions(shell);
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tsticker::{
+        download::default_file_name,
+        manifest::MANIFEST_FILE_NAME,
+        mock::MockServer,
+        pack::{Pack, PACK_FILE_NAME},
     };
 
-    let bot = build_bot(token).await;
-    let sticker_set = select_sticker_set(get_sticker_set(&bot, links).await);
-    download_sticker_set(&bot, sticker_set, output, None, fast_failure).await;
+    fn fixture(name: &str) -> serde_json::Value {
+        let path = format!(
+            "{}/../tsticker/tests/fixtures/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn count_stickers(dir: &std::path::Path) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                name != MANIFEST_FILE_NAME && name != PACK_FILE_NAME
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_download_sticker_set() {
+        let server = MockServer::start().await;
+        server.add_sticker_set(fixture("static_set.json"));
+        server.add_sticker_set(fixture("video_set.json"));
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+
+        let links = parse_links(vec![
+            "https://t.me/addstickers/myadestes_1_amashiro_natsuki_plus_nacho_neko".to_owned(),
+            "in_EDIHDC_by_NaiDrawBot".to_owned(),
+        ])
+        .unwrap();
+        let sticker_sets = get_sticker_set(&bot, links).await.unwrap();
+        assert_eq!(sticker_sets.len(), 2);
+
+        let dir = tempfile::tempdir().unwrap();
+        let expected: Vec<(PathBuf, String)> = sticker_sets
+            .iter()
+            .flat_map(|set| {
+                set.stickers.iter().map(|sticker| {
+                    (
+                        dir.path().join(&set.title).join(default_file_name(sticker)),
+                        sticker.file_unique_id.clone(),
+                    )
+                })
+            })
+            .collect();
+
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions {
+                jobs: 4,
+                ..DownloadOptions::new(dir.path().to_owned())
+            },
+            true,
+        )
+        .await;
+
+        assert_eq!(expected.len(), 7);
+        for (path, content) in expected {
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+        }
+        assert_eq!(server.hits("getFile"), 7);
+        assert_eq!(server.hits("file"), 7);
+    }
+
+    #[tokio::test]
+    async fn test_download_sticker_set_skip_failure() {
+        let server = MockServer::start().await;
+        server.add_sticker_set(fixture("video_set.json"));
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+        let sticker_sets = get_sticker_set(
+            &bot,
+            parse_links(vec!["in_EDIHDC_by_NaiDrawBot".to_owned()]).unwrap(),
+        )
+        .await
+        .unwrap();
+        let title = sticker_sets[0].title.clone();
+
+        server.fail("getFile", 400, "Bad Request: wrong file_id", None);
+        let dir = tempfile::tempdir().unwrap();
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions::new(dir.path().to_owned()),
+            false,
+        )
+        .await;
+
+        let written = count_stickers(&dir.path().join(title));
+        assert_eq!(written, 2);
+    }
+
+    #[tokio::test]
+    async fn test_download_sticker_set_rate_limited() {
+        let server = MockServer::start().await;
+        server.add_sticker_set(fixture("video_set.json"));
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+        let sticker_sets = get_sticker_set(
+            &bot,
+            parse_links(vec!["in_EDIHDC_by_NaiDrawBot".to_owned()]).unwrap(),
+        )
+        .await
+        .unwrap();
+        let title = sticker_sets[0].title.clone();
+
+        server.fail("getFile", 429, "Too Many Requests: retry after 0", Some(0));
+        server.fail("getFile", 429, "Too Many Requests: retry after 0", Some(0));
+        let dir = tempfile::tempdir().unwrap();
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions::new(dir.path().to_owned()),
+            true,
+        )
+        .await;
+
+        let written = count_stickers(&dir.path().join(title));
+        assert_eq!(written, 3);
+        assert_eq!(server.hits("getFile"), 5);
+    }
+
+    #[tokio::test]
+    async fn test_get_sticker_set_errors() {
+        let server = MockServer::start().await;
+        server.add_sticker_set(fixture("video_set.json"));
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+
+        let links = parse_links(vec![
+            "not_exists".to_owned(),
+            "in_EDIHDC_by_NaiDrawBot".to_owned(),
+        ])
+        .unwrap();
+        let sticker_sets = get_sticker_set(&bot, links.clone()).await.unwrap();
+        assert_eq!(sticker_sets.len(), 1);
+
+        server.fail("getStickerSet", 401, "Unauthorized", None);
+        let err = get_sticker_set(&bot, links).await.unwrap_err();
+        assert!(err.is_unauthorized());
+    }
+
+    #[tokio::test]
+    async fn test_login() {
+        let server = MockServer::start().await;
+        let args = |token: &str| {
+            Args::try_parse_from([
+                "tsticker-cli",
+                "-t",
+                token,
+                "--api-url",
+                server.url().as_str(),
+                "list",
+            ])
+            .unwrap()
+            .global
+        };
+        assert!(login(&args(server.token())).await.is_ok());
+        let err = login(&args("123:wrong")).await.unwrap_err();
+        assert!(err.to_string().contains("bot token is rejected"));
+    }
+
+    #[tokio::test]
+    async fn test_download_sticker_set_resume() {
+        let server = MockServer::start().await;
+        let mut set = fixture("video_set.json");
+        server.add_sticker_set(set.clone());
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let links = parse_links(vec!["in_EDIHDC_by_NaiDrawBot".to_owned()]).unwrap();
+
+        let sticker_sets = get_sticker_set(&bot, links.clone()).await.unwrap();
+        let set_dir = dir.path().join(&sticker_sets[0].title);
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions::new(dir.path().to_owned()),
+            true,
+        )
+        .await;
+        assert_eq!(server.hits("getFile"), 3);
+
+        // unchanged stickers are skipped, missing files are fetched again
+        let manifest = Manifest::load(&set_dir).await.unwrap();
+        let removed = manifest.stickers.values().next().unwrap();
+        std::fs::remove_file(set_dir.join(&removed.file_name)).unwrap();
+        let sticker_sets = get_sticker_set(&bot, links.clone()).await.unwrap();
+        let downloaded = download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions::new(dir.path().to_owned()),
+            true,
+        )
+        .await;
+        assert_eq!(server.hits("getFile"), 4);
+        assert!(set_dir.join(&removed.file_name).exists());
+        assert_eq!(downloaded.files.len(), 3);
+        assert_eq!(downloaded.written, [set_dir.join(&removed.file_name)]);
+
+        // stickers deleted from the set are removed only with prune
+        set["stickers"].as_array_mut().unwrap().remove(0);
+        server.add_sticker_set(set);
+        let sticker_sets = get_sticker_set(&bot, links.clone()).await.unwrap();
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions::new(dir.path().to_owned()),
+            true,
+        )
+        .await;
+        assert_eq!(Manifest::load(&set_dir).await.unwrap().stickers.len(), 3);
+
+        let sticker_sets = get_sticker_set(&bot, links).await.unwrap();
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions {
+                prune: true,
+                ..DownloadOptions::new(dir.path().to_owned())
+            },
+            true,
+        )
+        .await;
+        assert_eq!(Manifest::load(&set_dir).await.unwrap().stickers.len(), 2);
+        assert_eq!(count_stickers(&set_dir), 2);
+        let pack = Pack::load(&set_dir).await.unwrap();
+        assert_eq!(pack.stickers.len(), 2);
+        assert!(pack.stickers.iter().all(|s| s.file_name.is_some()));
+        assert_eq!(server.hits("getFile"), 4);
+    }
+
+    #[tokio::test]
+    async fn test_convert_stickers() {
+        let dir = tempfile::tempdir().unwrap();
+        let webp = dir.path().join("sticker.webp");
+        image::RgbaImage::new(64, 32)
+            .save_with_format(&webp, image::ImageFormat::WebP)
+            .unwrap();
+        let broken = dir.path().join("broken.webp");
+        std::fs::write(&broken, b"broken").unwrap();
+
+        let files = vec![
+            (webp.clone(), StickerFileExt::Webp),
+            (broken, StickerFileExt::Webp),
+        ];
+        let options = ConvertOptions {
+            size: Some(32),
+            ..ConvertOptions::new(OutputFormat::Png)
+        };
+        let (written, failures) = convert_stickers(files.clone(), options.clone(), 2).await;
+        assert_eq!(written, [dir.path().join("sticker.png")]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].stage, Stage::Convert);
+        let png = dir.path().join("sticker.png");
+        assert_eq!(image::image_dimensions(&png).unwrap(), (32, 16));
+
+        // converted files newer than their source are left alone
+        std::fs::write(&png, b"kept").unwrap();
+        convert_stickers(files[..1].to_vec(), options, 2).await;
+        assert_eq!(std::fs::read(&png).unwrap(), b"kept");
+    }
+
+    #[tokio::test]
+    async fn test_export_sticker_sets() {
+        let dir = tempfile::tempdir().unwrap();
+        let set: StickerSet = serde_json::from_value(fixture("static_set.json")).unwrap();
+        // events arrive out of order, exports follow the order of the set
+        let files: Vec<(String, Sticker, PathBuf)> = set
+            .stickers
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(idx, sticker)| {
+                let path = dir.path().join(format!("{}.webp", idx));
+                image::RgbaImage::new(16 * (idx as u32 + 1), 16)
+                    .save_with_format(&path, image::ImageFormat::WebP)
+                    .unwrap();
+                (set.name.clone(), sticker.clone(), path)
+            })
+            .collect();
+
+        let failures = export_sticker_sets(
+            std::slice::from_ref(&set),
+            &files,
+            &[ExportTarget::Discord, ExportTarget::Signal],
+            &DownloadOptions::new(dir.path().to_owned()),
+        )
+        .await;
+        assert!(failures.is_empty());
+        let discord = dir.path().join("export/discord").join(&set.title);
+        assert_eq!(
+            image::image_dimensions(discord.join("000.png")).unwrap(),
+            (320, 320)
+        );
+        assert_eq!(
+            image::image_dimensions(discord.join("003.png")).unwrap(),
+            (320, 80)
+        );
+        assert!(dir
+            .path()
+            .join("export/signal")
+            .join(&set.title)
+            .join("manifest.json")
+            .exists());
+    }
+
+    #[test]
+    fn test_filter_sticker_set() {
+        let sets: Vec<StickerSet> = ["static_set.json", "mask_set.json", "custom_emoji_set.json"]
+            .into_iter()
+            .map(|name| serde_json::from_value(fixture(name)).unwrap())
+            .collect();
+        assert_eq!(filter_sticker_set(sets.clone(), &[]).len(), 3);
+
+        let kinds = [StickerType::Regular, StickerType::CustomEmoji];
+        let names: Vec<String> = filter_sticker_set(sets, &kinds)
+            .into_iter()
+            .map(|set| set.name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "myadestes_1_amashiro_natsuki_plus_nacho_neko",
+                "PixelHearts"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_custom_emoji() {
+        let server = MockServer::start().await;
+        server.add_sticker_set(fixture("custom_emoji_set.json"));
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+
+        let links = parse_links(vec![
+            "https://t.me/addemoji/PixelHearts".to_owned(),
+            "5368324170671202286".to_owned(),
+            "1".to_owned(),
+        ])
+        .unwrap();
+        let mut sticker_sets = get_sticker_set(&bot, links).await.unwrap();
+        assert_eq!(sticker_sets.len(), 2);
+        assert_eq!(sticker_sets[0].name, "PixelHearts");
+        assert_eq!(sticker_sets[1].name, CUSTOM_EMOJI_SET);
+        assert_eq!(sticker_sets[1].stickers.len(), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloaded = download_sticker_set(
+            &bot,
+            sticker_sets.split_off(1),
+            DownloadOptions::new(dir.path().to_owned()),
+            true,
+        )
+        .await;
+        assert!(downloaded.failures.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(&downloaded.files[0].2).unwrap(),
+            "AgADPixel00"
+        );
+    }
+
+    #[test]
+    fn test_parse_links() {
+        let links = parse_links(vec![
+            "tg://addstickers?set=in_EDIHDC_by_NaiDrawBot".to_owned(),
+            "https://t.me/addemoji/PixelHearts/".to_owned(),
+        ])
+        .unwrap();
+        assert_eq!(links[0].set_name(), Some("in_EDIHDC_by_NaiDrawBot"));
+        assert_eq!(links[1], StickerLink::EmojiSet("PixelHearts".to_owned()));
+
+        let err = parse_links(vec![
+            "in_EDIHDC_by_NaiDrawBot".to_owned(),
+            "https://example.com/addstickers/Cats".to_owned(),
+        ])
+        .unwrap_err();
+        assert!(matches!(err, tsticker::error::Error::InvalidLink { .. }));
+    }
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+        let args = Args::try_parse_from([
+            "tsticker-cli",
+            "download",
+            "in_EDIHDC_by_NaiDrawBot",
+            "-o",
+            "stickers",
+            "--format",
+            "png",
+        ])
+        .unwrap();
+        assert_eq!(args.global.output, PathBuf::from("stickers"));
+        let Command::Download(download) = args.command else {
+            panic!("expect download, got {:?}", args.command);
+        };
+        assert_eq!(download.fetch.format, Some(OutputFormat::Png));
+        assert!(
+            Args::try_parse_from(["tsticker-cli", "sync", "--name-template", "{emoji}.{ext}"])
+                .is_err()
+        );
+
+        assert!(Args::try_parse_from(["tsticker-cli", "sync", "--size", "64"]).is_err());
+        assert!(Args::try_parse_from(["tsticker-cli", "in_EDIHDC_by_NaiDrawBot"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_sticker_sets() {
+        let server = MockServer::start().await;
+        server.add_sticker_set(fixture("static_set.json"));
+        server.add_sticker_set(fixture("custom_emoji_set.json"));
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+        let links = parse_links(vec![
+            "myadestes_1_amashiro_natsuki_plus_nacho_neko".to_owned(),
+            "5368324170671202286".to_owned(),
+        ])
+        .unwrap();
+        let sticker_sets = get_sticker_set(&bot, links).await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(local_sticker_sets(dir.path()).await.is_empty());
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions::new(dir.path().to_owned()),
+            true,
+        )
+        .await;
+        std::fs::create_dir(dir.path().join("unrelated")).unwrap();
+
+        let sets = local_sticker_sets(dir.path()).await;
+        assert_eq!(sets.len(), 2);
+        assert_eq!(
+            sync_links(&sets),
+            [StickerLink::StickerSet(
+                "myadestes_1_amashiro_natsuki_plus_nacho_neko".to_owned()
+            )]
+        );
+        let dirs: Vec<PathBuf> = sets.into_iter().map(|(dir, _)| dir).collect();
+        let files = recorded_stickers(&dirs).await;
+        assert_eq!(files.len(), 5);
+        assert!(files.iter().all(|(path, _)| path.exists()));
+    }
+
+    #[tokio::test]
+    async fn test_sync_into_found_dir() {
+        let server = MockServer::start().await;
+        let mut set = fixture("video_set.json");
+        server.add_sticker_set(set.clone());
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let links = parse_links(vec!["in_EDIHDC_by_NaiDrawBot".to_owned()]).unwrap();
+        let sticker_sets = get_sticker_set(&bot, links).await.unwrap();
+        let set_dir = dir.path().join(&sticker_sets[0].title);
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions::new(dir.path().to_owned()),
+            true,
+        )
+        .await;
+
+        // a renamed set is still synced into the directory it was found in
+        set["title"] = "Renamed".into();
+        server.add_sticker_set(set);
+        let sets = local_sticker_sets(dir.path()).await;
+        let sticker_sets = get_sticker_set(&bot, sync_links(&sets)).await.unwrap();
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions {
+                set_dirs: sync_dirs(&sets),
+                ..DownloadOptions::new(dir.path().to_owned())
+            },
+            true,
+        )
+        .await;
+        assert_eq!(Manifest::load(&set_dir).await.unwrap().title, "Renamed");
+        assert!(!dir.path().join("Renamed").exists());
+        assert_eq!(local_sticker_sets(dir.path()).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sync_keeps_naming() {
+        let server = MockServer::start().await;
+        let mut set = fixture("video_set.json");
+        let added = set["stickers"].as_array_mut().unwrap().pop().unwrap();
+        server.add_sticker_set(set.clone());
+        let bot = build_bot(server.token().to_owned(), Some(server.url()), false, None)
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let links = parse_links(vec!["in_EDIHDC_by_NaiDrawBot".to_owned()]).unwrap();
+        let template = NameTemplate::parse("{emoji}_{unique_id}.{ext}").unwrap();
+        let sticker_sets = get_sticker_set(&bot, links).await.unwrap();
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions {
+                name_template: Some(template),
+                folder: SetFolder::Name,
+                sanitizer: Sanitizer {
+                    ascii: true,
+                    ..Sanitizer::default()
+                },
+                ..DownloadOptions::new(dir.path().to_owned())
+            },
+            true,
+        )
+        .await;
+
+        // new stickers are named and transliterated like the ones downloaded before
+        set["stickers"].as_array_mut().unwrap().push(added);
+        server.add_sticker_set(set);
+        let sets = local_sticker_sets(dir.path()).await;
+        let sticker_sets = get_sticker_set(&bot, sync_links(&sets)).await.unwrap();
+        download_sticker_set(
+            &bot,
+            sticker_sets,
+            DownloadOptions {
+                set_dirs: sync_dirs(&sets),
+                recorded_naming: true,
+                ..DownloadOptions::new(dir.path().to_owned())
+            },
+            true,
+        )
+        .await;
+        let manifest = Manifest::load(&dir.path().join("in_EDIHDC_by_NaiDrawBot"))
+            .await
+            .unwrap();
+        assert!(manifest.ascii);
+        assert_eq!(manifest.stickers.len(), 3);
+        assert!(manifest.stickers.values().all(|e| e.file_name.is_ascii()));
+        assert!(manifest
+            .stickers
+            .iter()
+            .all(|(id, e)| e.file_name.contains(id.as_str())));
+    }
+
+    #[test]
+    fn test_select_sticker_set() {
+        assert!(matches_pattern("*_by_*bot", "in_EDIHDC_by_NaiDrawBot"));
+        assert!(matches_pattern("pixel?earts", "PixelHearts"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("pixel", "PixelHearts"));
+        assert!(!matches_pattern("*cats*", "PixelHearts"));
+
+        assert_eq!(Selection::new(false, Vec::new(), false), Selection::All);
+        assert_eq!(Selection::new(false, Vec::new(), true), Selection::Prompt);
+        assert_eq!(Selection::new(true, Vec::new(), true), Selection::All);
+
+        let sets: Vec<StickerSet> = ["static_set.json", "mask_set.json", "custom_emoji_set.json"]
+            .into_iter()
+            .map(|name| serde_json::from_value(fixture(name)).unwrap())
+            .collect();
+        assert_eq!(select_sticker_set(sets.clone(), &Selection::All).len(), 3);
+        let selection = Selection::new(true, vec!["pixel*".to_owned()], true);
+        let selected = select_sticker_set(sets, &selection);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name, "PixelHearts");
+    }
 }
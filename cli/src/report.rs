@@ -0,0 +1,253 @@
+//! Machine-readable output, for tools wrapping the binary.
+//!
+//! Once JSON output or NDJSON events are requested, human-readable messages move
+//! to stderr so that stdout only carries JSON.
+
+use std::{io::Write, path::PathBuf};
+
+use clap_derive::ValueEnum;
+use console::Term;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tsticker::{bot::StickerSet, download::DownloadEvent};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    // A JSON summary on stdout when the command finishes
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EventsFormat {
+    // One JSON object per line on stdout
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Config {
+    format: ReportFormat,
+    events: bool,
+}
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+pub fn init(format: ReportFormat, events: Option<EventsFormat>) {
+    CONFIG
+        .set(Config {
+            format,
+            events: events.is_some(),
+        })
+        .ok();
+}
+
+fn config() -> Config {
+    CONFIG.get().copied().unwrap_or_default()
+}
+
+pub fn is_json() -> bool {
+    config().format == ReportFormat::Json
+}
+
+/// Terminal for human-readable messages.
+pub fn term() -> Term {
+    let config = config();
+    if config.format == ReportFormat::Json || config.events {
+        Term::stderr()
+    } else {
+        Term::stdout()
+    }
+}
+
+fn print_json(value: &Value) {
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", value).unwrap();
+}
+
+/// Emit `event` as a line of NDJSON if events are requested.
+pub fn emit(event: &str, mut fields: Value) {
+    if !config().events {
+        return;
+    }
+    fields["event"] = json!(event);
+    print_json(&fields);
+}
+
+pub fn emit_download(event: &DownloadEvent) {
+    if config().events {
+        print_json(&download_event(event));
+    }
+}
+
+fn download_event(event: &DownloadEvent) -> Value {
+    let (name, mut fields) = match event {
+        DownloadEvent::SetStarted {
+            set,
+            title,
+            dir,
+            total,
+        } => (
+            "set_started",
+            json!({ "set": set, "title": title, "dir": dir, "total": total }),
+        ),
+        DownloadEvent::StickerSkipped { set, sticker, path } => (
+            "sticker_skipped",
+            json!({ "set": set, "file_unique_id": sticker.file_unique_id, "path": path }),
+        ),
+        DownloadEvent::StickerDownloaded {
+            set,
+            sticker,
+            path,
+            size,
+            cached,
+        } => (
+            "sticker_downloaded",
+            json!({
+                "set": set,
+                "file_unique_id": sticker.file_unique_id,
+                "path": path,
+                "size": size,
+                "cached": cached,
+            }),
+        ),
+        DownloadEvent::StickerFailed {
+            set,
+            sticker,
+            error,
+        } => (
+            "sticker_failed",
+            json!({
+                "set": set,
+                "file_unique_id": sticker.file_unique_id,
+                "error": error.to_string(),
+            }),
+        ),
+        DownloadEvent::StickersPruned { set, paths } => {
+            ("stickers_pruned", json!({ "set": set, "paths": paths }))
+        }
+        DownloadEvent::SetFailed { set, error } => (
+            "set_failed",
+            json!({ "set": set, "error": error.to_string() }),
+        ),
+        DownloadEvent::SetFinished {
+            set,
+            downloaded,
+            skipped,
+            failed,
+        } => (
+            "set_finished",
+            json!({
+                "set": set,
+                "downloaded": downloaded,
+                "skipped": skipped,
+                "failed": failed,
+            }),
+        ),
+    };
+    fields["event"] = json!(name);
+    fields
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Download,
+    Convert,
+    Export,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub stage: Stage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub set: Option<String>,
+    /// `file_unique_id` of the sticker, or the path of the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sticker: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SetSummary<'a> {
+    name: &'a str,
+    title: &'a str,
+    kind: &'a str,
+    stickers: usize,
+}
+
+/// Print the JSON summary of a `download` or `sync` if requested.
+pub fn summary(sets: &[StickerSet], files_written: &[PathBuf], failures: &[Failure]) {
+    if is_json() {
+        print_json(&summary_value(sets, files_written, failures));
+    }
+}
+
+fn summary_value(sets: &[StickerSet], files_written: &[PathBuf], failures: &[Failure]) -> Value {
+    let sets: Vec<SetSummary> = sets
+        .iter()
+        .map(|set| SetSummary {
+            name: &set.name,
+            title: &set.title,
+            kind: set.sticker_type.as_str(),
+            stickers: set.stickers.len(),
+        })
+        .collect();
+    json!({
+        "sets": sets,
+        "stickers": sets.iter().map(|set| set.stickers).sum::<usize>(),
+        "files_written": files_written,
+        "failures": failures,
+    })
+}
+
+/// Print `value` as the JSON summary of other commands if requested, returning
+/// whether it was printed.
+pub fn json(value: Value) -> bool {
+    if is_json() {
+        print_json(&value);
+    }
+    is_json()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary_value() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../tsticker/tests/fixtures/static_set.json"
+        );
+        let set: StickerSet =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let failure = Failure {
+            stage: Stage::Download,
+            set: Some(set.name.clone()),
+            sticker: Some(set.stickers[0].file_unique_id.clone()),
+            reason: "Bad Request: wrong file_id".to_owned(),
+        };
+        let summary = summary_value(
+            std::slice::from_ref(&set),
+            &[PathBuf::from("1.webp")],
+            &[failure],
+        );
+        assert_eq!(summary["sets"][0]["kind"], "regular");
+        assert_eq!(summary["stickers"], 4);
+        assert_eq!(summary["files_written"][0], "1.webp");
+        assert_eq!(summary["failures"][0]["stage"], "download");
+
+        let event = download_event(&DownloadEvent::StickerDownloaded {
+            set: set.name.clone(),
+            sticker: Box::new(set.stickers[1].clone()),
+            path: PathBuf::from("1.webp"),
+            size: 42,
+            cached: false,
+        });
+        assert_eq!(event["event"], "sticker_downloaded");
+        assert_eq!(event["file_unique_id"], set.stickers[1].file_unique_id);
+        assert_eq!(event["size"], 42);
+    }
+}
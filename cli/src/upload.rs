@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap_derive::Args;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Deserialize;
+use tsticker::{
+    bot::{Bot, StickerType},
+    error::Error,
+    link::StickerLink,
+    manifest::Manifest,
+    upload::{CloneReport, InputFile, InputSticker, StickerFormat, MAX_STICKERS_PER_CREATE},
+};
+
+use crate::{report, STYLE_PROGRESSBAR_LEN};
+
+#[derive(Debug, Args)]
+pub struct UploadArgs {
+    #[arg(help = "Directory of webp, png, tgs or webm stickers, uploaded in order of file name")]
+    pub dir: PathBuf,
+
+    #[arg(long, help = "Telegram user id owning the new sticker set")]
+    pub user_id: i64,
+
+    #[arg(
+        long,
+        help = "Name of the new sticker set, _by_<bot username> is appended if missing"
+    )]
+    pub name: String,
+
+    #[arg(long, help = "Title of the new sticker set")]
+    pub title: String,
+
+    #[arg(
+        long,
+        help = "JSON file mapping file names to emoji, e.g. {\"cat.webp\": \"🐱\", \"dog.webp\": [\"🐶\", \"🦴\"]}"
+    )]
+    pub emoji: Option<PathBuf>,
+
+    #[arg(long, help = "Emoji of stickers missing from the mapping")]
+    pub default_emoji: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "regular",
+        help = "regular, mask or custom_emoji"
+    )]
+    pub kind: StickerType,
+}
+
+#[derive(Debug, Args)]
+pub struct CloneArgs {
+    #[arg(help = "Link or name of the sticker or emoji set to copy")]
+    pub source: String,
+
+    #[arg(long, help = "Telegram user id owning the new sticker set")]
+    pub user_id: i64,
+
+    #[arg(
+        long,
+        help = "Name of the new sticker set, _by_<bot username> is appended if missing"
+    )]
+    pub name: String,
+
+    #[arg(
+        long,
+        help = "Title of the new sticker set, the source title by default"
+    )]
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EmojiList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<EmojiList> for Vec<String> {
+    fn from(list: EmojiList) -> Self {
+        match list {
+            EmojiList::One(emoji) => vec![emoji],
+            EmojiList::Many(list) => list,
+        }
+    }
+}
+
+/// Stickers in `dir` sorted by file name, with their emoji.
+///
+/// Emoji come from the `mapping` file, then from the manifest left by a download of
+/// the set, then `default_emoji`.
+async fn collect_stickers(
+    dir: &Path,
+    mapping: Option<&Path>,
+    default_emoji: Option<&str>,
+) -> anyhow::Result<Vec<(PathBuf, StickerFormat, Vec<String>)>> {
+    let mut mapping: HashMap<String, EmojiList> = match mapping {
+        Some(path) => {
+            let content =
+                std::fs::read(path).with_context(|| format!("fail to read {}", path.display()))?;
+            serde_json::from_slice(&content)
+                .with_context(|| format!("invalid emoji mapping {}", path.display()))?
+        }
+        None => HashMap::new(),
+    };
+    let manifest: HashMap<String, String> = Manifest::load(dir)
+        .await?
+        .stickers
+        .into_values()
+        .filter_map(|entry| Some((entry.file_name, entry.emoji?)))
+        .collect();
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("fail to read {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+
+    let mut stickers = Vec::new();
+    let mut missing = Vec::new();
+    for path in paths {
+        let Some(format) = StickerFormat::from_path(&path) else {
+            continue;
+        };
+        let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let emoji_list: Vec<String> = match mapping.remove(&file_name) {
+            Some(list) => list.into(),
+            None => match manifest
+                .get(&file_name)
+                .map(String::as_str)
+                .or(default_emoji)
+            {
+                Some(emoji) => vec![emoji.to_owned()],
+                None => {
+                    missing.push(file_name);
+                    continue;
+                }
+            },
+        };
+        if emoji_list.is_empty() || emoji_list.len() > 20 {
+            bail!("{} must have 1 to 20 emoji", file_name);
+        }
+        stickers.push((path, format, emoji_list));
+    }
+    if !missing.is_empty() {
+        bail!(
+            "no emoji for {}, add them to --emoji or set --default-emoji",
+            missing.join(", ")
+        );
+    }
+    if stickers.is_empty() {
+        bail!("no sticker found in {}", dir.display());
+    }
+    Ok(stickers)
+}
+
+fn set_link(name: String, kind: &StickerType) -> StickerLink {
+    match kind {
+        StickerType::CustomEmoji => StickerLink::EmojiSet(name),
+        _ => StickerLink::StickerSet(name),
+    }
+}
+
+/// Create a sticker set from the files in `args.dir`, returning its link.
+pub async fn upload_sticker_set(bot: &Bot, args: &UploadArgs) -> anyhow::Result<StickerLink> {
+    let term = report::term();
+    let stickers = collect_stickers(
+        &args.dir,
+        args.emoji.as_deref(),
+        args.default_emoji.as_deref(),
+    )
+    .await?;
+    let name = bot.sticker_set_name(&args.name);
+
+    term.write_line("Uploading stickers...")?;
+    let prog = if let Ok(style) = ProgressStyle::with_template(STYLE_PROGRESSBAR_LEN) {
+        ProgressBar::new(stickers.len() as u64).with_style(style)
+    } else {
+        ProgressBar::new(stickers.len() as u64)
+    };
+    let mut inputs = Vec::with_capacity(stickers.len());
+    for (path, format, emoji_list) in stickers {
+        prog.set_message(path.display().to_string());
+        let file = InputFile::read(&path).await?;
+        let uploaded = bot
+            .upload_sticker_file(args.user_id, &file, format)
+            .await
+            .with_context(|| format!("fail to upload {}", path.display()))?;
+        inputs.push(InputSticker::new(
+            InputFile::FileId(uploaded.file_id),
+            format,
+            emoji_list,
+        ));
+        prog.inc(1);
+    }
+    prog.finish_with_message("done");
+
+    term.write_line(&format!("Creating sticker set {}...", name))?;
+    let (first, rest) = inputs.split_at(inputs.len().min(MAX_STICKERS_PER_CREATE));
+    bot.create_new_sticker_set(args.user_id, &name, &args.title, &args.kind, first)
+        .await?;
+    for (idx, input) in rest.iter().enumerate() {
+        if let Err(e) = bot.add_sticker_to_set(args.user_id, &name, input).await {
+            return match bot.abandon_sticker_set(&name, first.len() + idx, e).await {
+                e @ Error::IncompleteSet { .. } => Err(e.into()),
+                e => Err(e).context(format!("fail to add stickers, {} was deleted", name)),
+            };
+        }
+    }
+    Ok(set_link(name, &args.kind))
+}
+
+/// Copy the set `args.source` into a new set owned by `args.user_id`, returning its link.
+pub async fn clone_sticker_set(bot: &Bot, args: &CloneArgs) -> anyhow::Result<StickerLink> {
+    let term = report::term();
+    let link = StickerLink::parse(&args.source)?;
+    let Some(source) = link.set_name() else {
+        bail!("{} is a custom emoji, not a set", link);
+    };
+    let source = bot.request_sticker_set(source).await?;
+    term.write_line(&format!(
+        "Cloning {} stickers of {}...",
+        source.stickers.len(),
+        source.name
+    ))?;
+    let title = args.title.as_deref().unwrap_or(&source.title);
+    let CloneReport {
+        name,
+        reused,
+        uploaded,
+    } = bot
+        .clone_sticker_set(args.user_id, &source, &args.name, title)
+        .await?;
+    term.write_line(&format!(
+        "Reused {} stickers, uploaded {} again",
+        reused, uploaded
+    ))?;
+    Ok(set_link(name, &source.sticker_type))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tsticker::mock::MockServer;
+
+    #[tokio::test]
+    async fn test_upload_sticker_set() {
+        let server = MockServer::start().await;
+        let bot = Bot::builder(server.token().to_owned())
+            .api_url(server.url())
+            .login()
+            .await
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        for idx in 0..52 {
+            std::fs::write(dir.path().join(format!("{:02}.webp", idx)), [idx]).unwrap();
+        }
+        std::fs::write(dir.path().join("52.webm"), b"video").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"skipped").unwrap();
+        let mapping = dir.path().join("emoji.json");
+        std::fs::write(&mapping, r#"{"00.webp": ["🐱", "😺"], "52.webm": "🎬"}"#).unwrap();
+
+        let mut args = UploadArgs {
+            dir: dir.path().to_owned(),
+            user_id: 42,
+            name: "cats".to_owned(),
+            title: "Cats".to_owned(),
+            emoji: Some(mapping),
+            default_emoji: None,
+            kind: StickerType::Regular,
+        };
+        let err = upload_sticker_set(&bot, &args).await.unwrap_err();
+        assert!(err.to_string().contains("01.webp"));
+
+        args.default_emoji = Some("🐈".to_owned());
+        let link = upload_sticker_set(&bot, &args).await.unwrap();
+        let name = link.set_name().unwrap();
+        assert_eq!(
+            link.to_string(),
+            "https://t.me/addstickers/cats_by_mock_bot"
+        );
+        assert_eq!(server.hits("uploadStickerFile"), 53);
+        assert_eq!(server.hits("addStickerToSet"), 3);
+
+        let set = bot.request_sticker_set(name).await.unwrap();
+        assert_eq!(set.stickers.len(), 53);
+        assert_eq!(set.stickers[0].emoji.as_deref(), Some("🐱"));
+        assert_eq!(set.stickers[1].emoji.as_deref(), Some("🐈"));
+        assert!(set.stickers[52].is_video);
+
+        let args = CloneArgs {
+            source: format!("https://t.me/addstickers/{}", name),
+            user_id: 42,
+            name: "more_cats".to_owned(),
+            title: None,
+        };
+        let link = clone_sticker_set(&bot, &args).await.unwrap();
+        assert_eq!(server.hits("uploadStickerFile"), 53);
+        let set = bot
+            .request_sticker_set(link.set_name().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(set.title, "Cats");
+        assert_eq!(set.stickers.len(), 53);
+    }
+}
@@ -0,0 +1,219 @@
+//! `getFile` and `getStickerSet` responses kept for a while, see
+//! [`BotBuilder::api_cache`](crate::bot::BotBuilder::api_cache).
+//!
+//! The Bot API has no conditional requests, so a set is fetched again once its entry
+//! expires. [`Bot::request_sticker_set_if_changed`](crate::bot::Bot::request_sticker_set_if_changed)
+//! tells whether it changed meanwhile.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{bot::StickerSet, cache::hex_encode, error::Result};
+
+/// Telegram keeps file paths valid for at least an hour.
+pub const DEFAULT_FILE_TTL: Duration = Duration::from_secs(50 * 60);
+pub const DEFAULT_SET_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry<T> {
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    value: T,
+}
+
+#[derive(Debug, Default)]
+struct Memory {
+    files: HashMap<String, Entry<String>>,
+    sets: HashMap<String, Entry<StickerSet>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiCache {
+    dir: Option<PathBuf>,
+    file_ttl: Duration,
+    set_ttl: Duration,
+    memory: Arc<Mutex<Memory>>,
+}
+
+impl Default for ApiCache {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            file_ttl: DEFAULT_FILE_TTL,
+            set_ttl: DEFAULT_SET_TTL,
+            memory: Arc::default(),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl ApiCache {
+    /// Kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also kept in `dir`, to be reused by later runs.
+    pub fn on_disk(dir: PathBuf) -> Self {
+        Self {
+            dir: Some(dir),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_file_ttl(mut self, ttl: Duration) -> Self {
+        self.file_ttl = ttl;
+        self
+    }
+
+    pub fn with_set_ttl(mut self, ttl: Duration) -> Self {
+        self.set_ttl = ttl;
+        self
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    fn entry_path(&self, kind: &str, key: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(kind).join(format!("{}.json", hex_encode(key))))
+    }
+
+    async fn load<T: DeserializeOwned>(&self, kind: &str, key: &str) -> Option<Entry<T>> {
+        let content = tokio::fs::read(self.entry_path(kind, key)?).await.ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    async fn store<T: Serialize>(&self, kind: &str, key: &str, entry: &Entry<T>) {
+        let Some(path) = self.entry_path(kind, key) else {
+            return;
+        };
+        let res = async {
+            tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+            let tmp = path.with_extension("json.tmp");
+            let content = serde_json::to_vec(entry).map_err(std::io::Error::other)?;
+            tokio::fs::write(&tmp, content).await?;
+            tokio::fs::rename(&tmp, &path).await
+        }
+        .await;
+        if let Err(err) = res {
+            warn!("fail to cache {} {}: {}", kind, key, err);
+        }
+    }
+
+    async fn remove(&self, kind: &str, key: &str) {
+        if let Some(path) = self.entry_path(kind, key) {
+            tokio::fs::remove_file(path).await.ok();
+        }
+    }
+
+    pub(crate) async fn file_path(&self, file_id: &str) -> Option<String> {
+        let fresh = |entry: &Entry<String>| now() < entry.fetched_at + self.file_ttl.as_secs();
+        let cached = self.memory.lock().unwrap().files.get(file_id).cloned();
+        let entry = match cached {
+            Some(entry) => entry,
+            None => {
+                let entry = self.load::<String>("files", file_id).await?;
+                self.memory
+                    .lock()
+                    .unwrap()
+                    .files
+                    .insert(file_id.to_owned(), entry.clone());
+                entry
+            }
+        };
+        fresh(&entry).then_some(entry.value)
+    }
+
+    pub(crate) async fn insert_file_path(&self, file_id: &str, path: &str) {
+        let entry = Entry {
+            fetched_at: now(),
+            value: path.to_owned(),
+        };
+        self.store("files", file_id, &entry).await;
+        self.memory
+            .lock()
+            .unwrap()
+            .files
+            .insert(file_id.to_owned(), entry);
+    }
+
+    /// Set names are case-insensitive.
+    fn set_key(name: &str) -> String {
+        name.to_lowercase()
+    }
+
+    async fn set_entry(&self, name: &str) -> Option<Entry<StickerSet>> {
+        let key = Self::set_key(name);
+        let cached = self.memory.lock().unwrap().sets.get(&key).cloned();
+        match cached {
+            Some(entry) => Some(entry),
+            None => {
+                let entry = self.load::<StickerSet>("sets", &key).await?;
+                self.memory.lock().unwrap().sets.insert(key, entry.clone());
+                Some(entry)
+            }
+        }
+    }
+
+    pub(crate) async fn sticker_set(&self, name: &str) -> Option<StickerSet> {
+        let entry = self.set_entry(name).await?;
+        (now() < entry.fetched_at + self.set_ttl.as_secs()).then_some(entry.value)
+    }
+
+    pub(crate) async fn insert_sticker_set(&self, set: &StickerSet) {
+        let key = Self::set_key(&set.name);
+        let entry = Entry {
+            fetched_at: now(),
+            value: set.clone(),
+        };
+        self.store("sets", &key, &entry).await;
+        self.memory.lock().unwrap().sets.insert(key, entry);
+    }
+
+    /// Drop the set `name` after the bot changed it.
+    pub(crate) async fn forget_sticker_set(&self, name: &str) {
+        let key = Self::set_key(name);
+        self.memory.lock().unwrap().sets.remove(&key);
+        self.remove("sets", &key).await;
+    }
+
+    /// Drop every set, when the bot changed a set it can't tell.
+    pub(crate) async fn forget_sticker_sets(&self) -> Result<()> {
+        self.memory.lock().unwrap().sets.clear();
+        if let Some(dir) = &self.dir {
+            remove_dir(&dir.join("sets")).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn clear(&self) -> Result<()> {
+        self.memory.lock().unwrap().files.clear();
+        self.forget_sticker_sets().await?;
+        if let Some(dir) = &self.dir {
+            remove_dir(&dir.join("files")).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn remove_dir(dir: &Path) -> Result<()> {
+    match tokio::fs::remove_dir_all(dir).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
@@ -1,24 +1,105 @@
-use std::{fs::{FileTimes, FileType}, rc::Rc, sync::Arc};
+use std::{fmt, path::Path, str::FromStr, sync::Arc, time::Duration};
 
+use bytes::Bytes;
+use futures_util::{stream::BoxStream, StreamExt, TryStreamExt};
 use log::{debug, info};
-use reqwest::{Client, Response, Url};
+use reqwest::{Client, RequestBuilder, StatusCode, Url};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio_util::io::ReaderStream;
 
 use crate::{
-    bot,
+    api_cache::ApiCache,
     error::{Error, Result},
+    retry::RetryPolicy,
 };
 
+pub const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
 #[derive(Debug, Clone)]
 pub struct Bot {
-    token: String,
-    client: Client,
+    pub(crate) client: Client,
+    pub(crate) config: Arc<BotConfig>,
+    pub(crate) api_cache: Option<ApiCache>,
     info: Arc<BotInfo>,
 }
 
+/// Connection settings of a [`Bot`].
+///
+/// `api_url` is used for both the method endpoint (`<api_url>/bot<token>/<method>`)
+/// and the file endpoint (`<api_url>/file/bot<token>/<file_path>`).
+/// Set `local_mode` when talking to a telegram-bot-api server started with `--local`,
+/// whose `getFile` returns absolute paths on the local filesystem.
+#[derive(Debug, Clone)]
+pub struct BotConfig {
+    pub token: String,
+    pub api_url: Url,
+    pub local_mode: bool,
+    pub retry: RetryPolicy,
+}
+
+impl BotConfig {
+    pub fn new(token: String) -> Self {
+        Self {
+            token,
+            api_url: Url::parse(DEFAULT_API_URL).unwrap(),
+            local_mode: false,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+pub struct BotBuilder {
+    config: BotConfig,
+    client: Option<Client>,
+    api_cache: Option<ApiCache>,
+}
+
+impl BotBuilder {
+    pub fn new(token: String) -> Self {
+        Self {
+            config: BotConfig::new(token),
+            client: None,
+            api_cache: None,
+        }
+    }
+
+    pub fn api_url(mut self, url: Url) -> Self {
+        self.config.api_url = url;
+        self
+    }
+
+    pub fn local_mode(mut self, local_mode: bool) -> Self {
+        self.config.local_mode = local_mode;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Reuse `getFile` and `getStickerSet` responses until they expire.
+    /// Sets changed through the bot are fetched again.
+    pub fn api_cache(mut self, cache: ApiCache) -> Self {
+        self.api_cache = Some(cache);
+        self
+    }
+
+    pub async fn login(self) -> Result<Bot> {
+        let client = self.client.unwrap_or_default();
+        let mut bot = Bot::login_with_client(client, self.config).await?;
+        bot.api_cache = self.api_cache;
+        Ok(bot)
+    }
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct TelegramResp<T> {
-    ok: bool,
     result: T,
 }
 
@@ -26,27 +107,58 @@ struct TelegramResp<T> {
 struct TelegramStatus {
     ok: bool,
     error_code: Option<i32>,
+    description: Option<String>,
+    parameters: Option<ResponseParameters>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct ResponseParameters {
+    migrate_to_chat_id: Option<i64>,
+    retry_after: Option<u64>,
+}
+
+impl TelegramStatus {
+    fn into_error(self, status: StatusCode) -> Error {
+        let parameters = self.parameters.unwrap_or_default();
+        Error::Api {
+            code: self.error_code.unwrap_or(status.as_u16() as i32),
+            description: self.description.unwrap_or_default(),
+            retry_after: parameters.retry_after.map(Duration::from_secs),
+            migrate_to_chat_id: parameters.migrate_to_chat_id,
+        }
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
 pub struct BotInfo {
-    id: i64,
+    pub id: i64,
     pub first_name: String,
     pub username: String,
 }
 
-async fn request<T>(client: &Client, url: Url) -> Result<T>
+pub(crate) async fn request<T>(req: RequestBuilder, target: &str) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let target = url.path().to_owned();
-    let resp = client.get(url).send().await?.text().await?;
+    let resp = req.send().await?;
+    let status = resp.status();
+    let resp = resp.text().await?;
     debug!("request {}: {}", target, resp);
-    if !serde_json::from_str::<TelegramStatus>(&resp)
-        .map_err(Error::ResponseJsonError)?
-        .ok
-    {
-        return Err(Error::BotError(resp));
+    let telegram_status = match serde_json::from_str::<TelegramStatus>(&resp) {
+        Ok(telegram_status) => telegram_status,
+        // e.g. an HTML page from a reverse proxy
+        Err(_) if !status.is_success() => {
+            return Err(Error::Api {
+                code: status.as_u16() as i32,
+                description: status.canonical_reason().unwrap_or_default().to_owned(),
+                retry_after: None,
+                migrate_to_chat_id: None,
+            })
+        }
+        Err(e) => return Err(Error::ResponseJsonError(e)),
+    };
+    if !telegram_status.ok {
+        return Err(telegram_status.into_error(status));
     }
 
     let ans = serde_json::from_str::<TelegramResp<T>>(&resp)
@@ -55,139 +167,281 @@ where
     Ok(ans)
 }
 
+pub(crate) fn endpoint(base: &Url, segments: &[&str]) -> Url {
+    let mut entry = base.as_str().trim_end_matches('/').to_owned();
+    for segment in segments {
+        entry.push('/');
+        entry.push_str(segment.trim_start_matches('/'));
+    }
+    Url::parse(&entry).expect("Unexpected error when build endpoint url")
+}
+
 async fn request_telegram<T>(
     client: &Client,
-    token: &str,
+    config: &BotConfig,
     path: &str,
     params: &[(&'static str, &str)],
 ) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let mut entry = format!("https://api.telegram.org/bot{}", &token);
-    if !path.starts_with('/') {
-        entry.push_str("/");
-    }
-    entry.push_str(path);
-    let url = reqwest::Url::parse_with_params(&entry, params)
-        .expect("Unexpected error when parse url with params");
-    request(client, url).await
+    let mut url = endpoint(&config.api_url, &[&format!("bot{}", config.token), path]);
+    url.query_pairs_mut().extend_pairs(params);
+    config
+        .retry
+        .run(path, || request(client.get(url.clone()), path))
+        .await
+}
+
+pub trait TelegramFile: Send + Sync {
+    fn file_id(&self) -> &str;
+    fn file_size(&self) -> Option<u64>;
+}
+
+/// A file ready to be downloaded, see [`Bot::request_file_id`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct File {
+    pub file_id: String,
+    pub file_unique_id: String,
+    pub file_size: Option<u64>,
+    pub file_path: Option<String>,
 }
 
-pub trait TelegramFile{
-    fn file_id(&self)-> &str;
-    fn file_size(&self) -> u64;
+impl TelegramFile for File {
+    fn file_id(&self) -> &str {
+        &self.file_id
+    }
+
+    fn file_size(&self) -> Option<u64> {
+        self.file_size
+    }
 }
 
-#[derive(Debug, serde::Deserialize, Clone)]
-pub struct ThumbFile {
+/// One size of a photo or a thumbnail.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PhotoSize {
     pub file_id: String,
     pub file_unique_id: String,
-    pub file_size: u64,
     pub width: u32,
-    pub height: u32
+    pub height: u32,
+    pub file_size: Option<u64>,
 }
 
-impl TelegramFile for ThumbFile {
-    fn file_id(&self)-> &str {
+impl TelegramFile for PhotoSize {
+    fn file_id(&self) -> &str {
         &self.file_id
     }
 
-    fn file_size(&self) -> u64 {
+    fn file_size(&self) -> Option<u64> {
         self.file_size
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskPoint {
+    Forehead,
+    Eyes,
+    Mouth,
+    Chin,
+}
+
+/// Where a mask is placed on faces by default.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MaskPosition {
+    pub point: MaskPoint,
+    /// Shift by X-axis measured in widths of the mask scaled to the face size.
+    pub x_shift: f64,
+    /// Shift by Y-axis measured in heights of the mask scaled to the face size.
+    pub y_shift: f64,
+    pub scale: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StickerFileExt {
     Webp,
-    Webm
+    Webm,
+    /// gzip-compressed Lottie animation, see [`crate::tgs`]
+    Tgs,
 }
-impl ToString for StickerFileExt{
-    fn to_string(&self) -> String {
+impl fmt::Display for StickerFileExt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            StickerFileExt::Webp => "webp".to_string(),
-            StickerFileExt::Webm => "webm".to_string(),
+            StickerFileExt::Webp => f.write_str("webp"),
+            StickerFileExt::Webm => f.write_str("webm"),
+            StickerFileExt::Tgs => f.write_str("tgs"),
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, Clone)]
-pub struct Sticker{
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Sticker {
+    pub file_id: String,
+    pub file_unique_id: String,
+    #[serde(rename = "type")]
+    pub ty: StickerType,
     pub width: u32,
     pub height: u32,
-    pub emoji: String,
-    // useless
-    // set_name: String, 
     pub is_animated: bool,
     pub is_video: bool,
-    #[serde(rename = "type")]
-    pub ty: String,
-    pub thumbnail: ThumbFile,
-    pub thumb: ThumbFile,
-    pub file_id: String,
-    pub file_unique_id: String,
-    pub file_size: u64
+    pub thumbnail: Option<PhotoSize>,
+    /// Old name of `thumbnail`, only sent by outdated Bot API servers.
+    pub thumb: Option<PhotoSize>,
+    pub emoji: Option<String>,
+    pub set_name: Option<String>,
+    /// Premium animation of premium regular stickers.
+    pub premium_animation: Option<File>,
+    /// For mask stickers.
+    pub mask_position: Option<MaskPosition>,
+    /// For custom emoji stickers.
+    pub custom_emoji_id: Option<String>,
+    /// Custom emoji stickers repainted to the text color in messages.
+    #[serde(default)]
+    pub needs_repainting: bool,
+    pub file_size: Option<u64>,
 }
 
 impl Sticker {
-    pub fn file_ext(&self)->StickerFileExt{
+    pub fn thumbnail(&self) -> Option<&PhotoSize> {
+        self.thumbnail.as_ref().or(self.thumb.as_ref())
+    }
+
+    /// Telegram never sets both `is_animated` and `is_video`, video wins if it does.
+    pub fn file_ext(&self) -> StickerFileExt {
         match (self.is_animated, self.is_video) {
             (false, false) => StickerFileExt::Webp,
-            (true, false) => StickerFileExt::Webm,
-            (false, true) => StickerFileExt::Webm,
-            _ => unimplemented!(),
+            (true, false) => StickerFileExt::Tgs,
+            (_, true) => StickerFileExt::Webm,
         }
     }
 }
 
-
-
-impl TelegramFile for Sticker{
-    fn file_id(&self)-> &str {
+impl TelegramFile for Sticker {
+    fn file_id(&self) -> &str {
         &self.file_id
     }
 
-    fn file_size(&self) -> u64 {
+    fn file_size(&self) -> Option<u64> {
         self.file_size
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
-pub struct StickerSet{
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StickerSet {
     pub name: String,
     pub title: String,
-    pub sticker_type: String,
-    pub stickers: Vec<Sticker>
+    pub sticker_type: StickerSetType,
+    pub stickers: Vec<Sticker>,
+    pub thumbnail: Option<PhotoSize>,
 }
 
-impl ToString for StickerSet{
-    fn to_string(&self) -> String {
-        self.title.clone()
+impl fmt::Display for StickerSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.title)
     }
 }
 
-
-#[derive(Deserialize, Debug)]
-struct FileInfoResp {
-    file_path: String,
-    // useless attr
-    // file_id: String,
-    // file_size: u64,
-    // file_unique_id: String,
+impl StickerSet {
+    /// Changes whenever the title, the stickers or their order or emoji change.
+    /// Stable across runs and platforms.
+    pub fn fingerprint(&self) -> String {
+        // FNV-1a, the hasher of std may change between releases
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |s: &str| {
+            for b in s.bytes().chain([0]) {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        write(&self.title);
+        write(self.sticker_type.as_str());
+        for sticker in self.stickers.iter() {
+            write(&sticker.file_unique_id);
+            write(sticker.emoji.as_deref().unwrap_or_default());
+        }
+        format!("{:016x}", hash)
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TelegramFileID(String);
-impl ToString for TelegramFileID{
-    fn to_string(&self) -> String {
-        self.0.clone()
+impl fmt::Display for TelegramFileID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
+pub type FileStream = BoxStream<'static, Result<Bytes>>;
+
+/// Most custom emoji ids `getCustomEmojiStickers` accepts at once.
+pub const MAX_CUSTOM_EMOJI_IDS: usize = 200;
+
+/// Emoji of stickers without one, where other platforms or methods require it.
+pub(crate) const FALLBACK_EMOJI: &str = "\u{1f642}";
+
+/// Kind of a sticker. Kinds added by newer Bot API versions end up in `Unknown`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
 pub enum StickerType {
-    Regular
+    Regular,
+    Mask,
+    CustomEmoji,
+    Unknown(String),
+}
+
+/// All stickers of a set share the kind of the set.
+pub type StickerSetType = StickerType;
+
+impl StickerType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            StickerType::Regular => "regular",
+            StickerType::Mask => "mask",
+            StickerType::CustomEmoji => "custom_emoji",
+            StickerType::Unknown(ty) => ty,
+        }
+    }
+}
+
+impl From<String> for StickerType {
+    fn from(ty: String) -> Self {
+        match ty.as_str() {
+            "regular" => StickerType::Regular,
+            "mask" => StickerType::Mask,
+            "custom_emoji" => StickerType::CustomEmoji,
+            _ => StickerType::Unknown(ty),
+        }
+    }
+}
+
+impl From<StickerType> for String {
+    fn from(ty: StickerType) -> Self {
+        match ty {
+            StickerType::Unknown(ty) => ty,
+            ty => ty.as_str().to_owned(),
+        }
+    }
+}
+
+impl FromStr for StickerType {
+    type Err = String;
+
+    /// Unlike deserializing, unknown kinds are rejected.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match StickerType::from(s.to_owned()) {
+            StickerType::Unknown(ty) => Err(format!(
+                "unknown sticker kind {}, expect regular, mask or custom_emoji",
+                ty
+            )),
+            ty => Ok(ty),
+        }
+    }
 }
-pub enum StickerSetType {
-    Regular
+
+impl fmt::Display for StickerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl Bot {
@@ -195,90 +449,137 @@ impl Bot {
         &self.info
     }
 
+    pub fn config(&self) -> &BotConfig {
+        &self.config
+    }
+
+    pub fn builder(token: String) -> BotBuilder {
+        BotBuilder::new(token)
+    }
+
     pub async fn login(token: String) -> Result<Bot> {
-        let client = reqwest::Client::new();
+        Self::login_with(BotConfig::new(token)).await
+    }
 
-        let info = request_telegram::<BotInfo>(&client, &token, "getMe", &[]).await?;
+    pub async fn login_with(config: BotConfig) -> Result<Bot> {
+        Self::login_with_client(Client::new(), config).await
+    }
+
+    async fn login_with_client(client: Client, config: BotConfig) -> Result<Bot> {
+        let info = request_telegram::<BotInfo>(&client, &config, "getMe", &[]).await?;
 
         Ok(Self {
-            token,
             client,
+            config: Arc::new(config),
+            api_cache: None,
             info: Arc::new(info),
         })
     }
 
     pub async fn request_sticker_set(&self, name: &str) -> Result<StickerSet> {
+        if let Some(cache) = &self.api_cache {
+            if let Some(sticker_set) = cache.sticker_set(name).await {
+                return Ok(sticker_set);
+            }
+        }
         let sticker_set = request_telegram::<StickerSet>(
             &self.client,
-            &self.token,
+            &self.config,
             "getStickerSet",
             &[("name", name)],
         )
         .await?;
         info!("stickers: {:?}", &sticker_set);
+        if let Some(cache) = &self.api_cache {
+            cache.insert_sticker_set(&sticker_set).await;
+        }
 
         Ok(sticker_set)
     }
 
-    pub async fn request_file_id(&self, sticker: &dyn TelegramFile) -> Result<TelegramFileID>{
-        let file: FileInfoResp = request_telegram::<FileInfoResp>(
-            &self.client,
-            &self.token,
-            "getFile",
-            &[("file_id", sticker.file_id())],
-        ).await?;
-        Ok(TelegramFileID(file.file_path))
-    }
-
-    pub async fn download_file(&self, path: &TelegramFileID)->Result<Response>{
-        let link = format!("https://api.telegram.org/file/bot{}/{}", self.token, &path.0);
-        let request = self.client.get(link);
-        Ok(request.send().await?)
-    }
-}
-
-mod test {
-    use super::*;
-
-    fn token()->String{
-        std::env::var("TSTICKER_TOKEN").unwrap()
+    /// Like [`Bot::request_sticker_set`], but `None` if the set still has `fingerprint`,
+    /// from [`StickerSet::fingerprint`] of a copy fetched before, so that callers can
+    /// skip sets that did not change.
+    pub async fn request_sticker_set_if_changed(
+        &self,
+        name: &str,
+        fingerprint: &str,
+    ) -> Result<Option<StickerSet>> {
+        let sticker_set = self.request_sticker_set(name).await?;
+        Ok((sticker_set.fingerprint() != fingerprint).then_some(sticker_set))
     }
 
-
-    #[tokio::test]
-    async fn test_login() {
-        Bot::login(token())
-            .await
-            .expect("Fail to login");
+    /// Stickers of custom emoji, unknown ids are left out.
+    ///
+    /// Requests are split into batches of [`MAX_CUSTOM_EMOJI_IDS`].
+    pub async fn get_custom_emoji_stickers<S: AsRef<str>>(
+        &self,
+        ids: &[S],
+    ) -> Result<Vec<Sticker>> {
+        let mut stickers = Vec::with_capacity(ids.len());
+        for batch in ids.chunks(MAX_CUSTOM_EMOJI_IDS) {
+            let batch: Vec<&str> = batch.iter().map(AsRef::as_ref).collect();
+            let batch = serde_json::to_string(&batch).map_err(Error::ResponseJsonError)?;
+            stickers.extend(
+                request_telegram::<Vec<Sticker>>(
+                    &self.client,
+                    &self.config,
+                    "getCustomEmojiStickers",
+                    &[("custom_emoji_ids", &batch)],
+                )
+                .await?,
+            );
+        }
+        Ok(stickers)
     }
 
-    #[tokio::test]
-    #[should_panic]
-    async fn test_login_fail() {
-        let fake_token = token()
-            .chars()
-            .map(
-                |ch| match (ch.is_alphabetic(), ch.is_uppercase(), ch.is_numeric()) {
-                    (true, true, _) => 'A',
-                    (true, false, _) => 'a',
-                    (_, _, true) => '0',
-                    (_, _, _) => ch,
-                },
-            )
-            .collect::<String>();
-        Bot::login(dbg!(fake_token)).await.unwrap();
+    pub async fn request_file_id(&self, sticker: &dyn TelegramFile) -> Result<TelegramFileID> {
+        if let Some(cache) = &self.api_cache {
+            if let Some(path) = cache.file_path(sticker.file_id()).await {
+                return Ok(TelegramFileID(path));
+            }
+        }
+        let file = request_telegram::<File>(
+            &self.client,
+            &self.config,
+            "getFile",
+            &[("file_id", sticker.file_id())],
+        )
+        .await?;
+        // Telegram omits the path of files too big to download
+        let path = file.file_path.ok_or_else(|| Error::Api {
+            code: 400,
+            description: "Bad Request: file is too big".to_owned(),
+            retry_after: None,
+            migrate_to_chat_id: None,
+        })?;
+        if let Some(cache) = &self.api_cache {
+            cache.insert_file_path(sticker.file_id(), &path).await;
+        }
+        Ok(TelegramFileID(path))
     }
 
-    #[tokio::test]
-    async fn test_list_sticker() {
-        let test_list = vec![
-            "myadestes_1_amashiro_natsuki_plus_nacho_neko", // plain
-            "in_EDIHDC_by_NaiDrawBot",                      // animated
-        ];
-        let bot = Bot::login(token()).await.unwrap();
-        for name in test_list {
-            let sticker_set = bot.request_sticker_set(name).await.unwrap();
-            println!("{:?}", sticker_set);
+    pub async fn download_file(&self, path: &TelegramFileID) -> Result<FileStream> {
+        if self.config.local_mode && Path::new(&path.0).is_absolute() {
+            let file = tokio::fs::File::open(&path.0).await?;
+            return Ok(ReaderStream::new(file).map_err(Error::from).boxed());
         }
+        let link = endpoint(
+            &self.config.api_url,
+            &["file", &format!("bot{}", self.config.token), &path.0],
+        );
+        let resp = self
+            .config
+            .retry
+            .run("file", || async {
+                Ok(self
+                    .client
+                    .get(link.clone())
+                    .send()
+                    .await?
+                    .error_for_status()?)
+            })
+            .await?;
+        Ok(resp.bytes_stream().map_err(Error::from).boxed())
     }
 }
@@ -0,0 +1,280 @@
+//! Sticker files shared by every sticker set and run, keyed by `file_unique_id`.
+//!
+//! Telegram gives the same file the same `file_unique_id` in every set, so a sticker
+//! found in the cache is placed into the set directory without downloading it again.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{bot::Sticker, error::Result};
+
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// How a cached file was placed at its destination.
+///
+/// Never a hard link, which would let an output file edited in place change the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// Copy-on-write clone, on filesystems such as Btrfs, XFS and APFS.
+    Reflink,
+    Copy,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub file_unique_id: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct StickerCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl StickerCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// `$XDG_CACHE_HOME/tsticker`, or where the platform keeps caches.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("tsticker"))
+    }
+
+    /// Total size in bytes [`StickerCache::evict`] shrinks the cache to.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    fn files_dir(&self) -> PathBuf {
+        self.dir.join("files")
+    }
+
+    /// Ids may only differ in case, so they are hex encoded for case-insensitive filesystems.
+    fn path(&self, file_unique_id: &str) -> PathBuf {
+        self.files_dir().join(hex_encode(file_unique_id))
+    }
+
+    /// The cached file of `sticker`, if its size matches what Telegram reported.
+    pub async fn get(&self, sticker: &Sticker) -> Option<CacheEntry> {
+        let path = self.path(&sticker.file_unique_id);
+        let meta = tokio::fs::metadata(&path).await.ok()?;
+        if !meta.is_file() || sticker.file_size.is_some_and(|size| size != meta.len()) {
+            return None;
+        }
+        let last_used = SystemTime::now();
+        touch(path.clone(), last_used).await;
+        Some(CacheEntry {
+            file_unique_id: sticker.file_unique_id.clone(),
+            path,
+            size: meta.len(),
+            last_used,
+        })
+    }
+
+    /// Store the downloaded file of `sticker` at `src`.
+    pub async fn insert(&self, sticker: &Sticker, src: &Path) -> Result<()> {
+        tokio::fs::create_dir_all(self.files_dir()).await?;
+        place(src, &self.path(&sticker.file_unique_id)).await?;
+        Ok(())
+    }
+
+    /// Put the cached file `entry` at `dst`, replacing it.
+    pub async fn place(&self, entry: &CacheEntry, dst: &Path) -> Result<Placement> {
+        place(&entry.path, dst).await
+    }
+
+    pub async fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let mut dir = match tokio::fs::read_dir(self.files_dir()).await {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut entries = Vec::new();
+        while let Some(entry) = dir.next_entry().await? {
+            // Skip files being placed
+            let Some(file_unique_id) = entry.file_name().to_str().and_then(hex_decode) else {
+                continue;
+            };
+            let meta = entry.metadata().await?;
+            entries.push(CacheEntry {
+                file_unique_id,
+                path: entry.path(),
+                size: meta.len(),
+                last_used: meta.modified()?,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Remove the least recently used files until the cache fits in its maximum size,
+    /// returning the removed files.
+    pub async fn evict(&self) -> Result<Vec<CacheEntry>> {
+        self.evict_to(self.max_size).await
+    }
+
+    pub async fn evict_to(&self, max_size: u64) -> Result<Vec<CacheEntry>> {
+        let mut entries = self.entries().await?;
+        entries.sort_by_key(|entry| entry.last_used);
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut removed = Vec::new();
+        for entry in entries {
+            if size <= max_size {
+                break;
+            }
+            tokio::fs::remove_file(&entry.path).await?;
+            size -= entry.size;
+            removed.push(entry);
+        }
+        Ok(removed)
+    }
+
+    pub async fn clear(&self) -> Result<Vec<CacheEntry>> {
+        self.evict_to(0).await
+    }
+}
+
+/// Place `src` at `dst` through a temporary file, by reflink, or by copy where the
+/// filesystem has no reflinks.
+async fn place(src: &Path, dst: &Path) -> Result<Placement> {
+    let file_name = dst.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dst.with_file_name(format!(".{}.part", file_name));
+    tokio::fs::remove_file(&tmp).await.ok();
+
+    let placement = {
+        let (src, tmp) = (src.to_owned(), tmp.clone());
+        tokio::task::spawn_blocking(move || reflink_copy::reflink(&src, &tmp))
+            .await
+            .map_err(std::io::Error::other)?
+    };
+    let placement = match placement {
+        Ok(()) => Placement::Reflink,
+        Err(_) => {
+            tokio::fs::copy(src, &tmp).await?;
+            Placement::Copy
+        }
+    };
+    if let Err(err) = tokio::fs::rename(&tmp, dst).await {
+        tokio::fs::remove_file(&tmp).await.ok();
+        return Err(err.into());
+    }
+    Ok(placement)
+}
+
+/// Mark a cached file as used, for eviction.
+async fn touch(path: PathBuf, time: SystemTime) {
+    tokio::task::spawn_blocking(move || {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(time))
+    })
+    .await
+    .ok();
+}
+
+pub(crate) fn hex_encode(id: &str) -> String {
+    id.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(name: &str) -> Option<String> {
+    let bytes = (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::bot::StickerSet;
+
+    #[tokio::test]
+    async fn test_cache() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/static_set.json"
+        );
+        let mut set: StickerSet =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = StickerCache::new(dir.path().join("cache")).with_max_size(10);
+        assert!(cache.entries().await.unwrap().is_empty());
+
+        for (idx, sticker) in set.stickers.iter_mut().take(2).enumerate() {
+            let src = dir.path().join(format!("{}.webp", idx));
+            std::fs::write(&src, [idx as u8; 6]).unwrap();
+            sticker.file_size = Some(6);
+            cache.insert(sticker, &src).await.unwrap();
+        }
+        let first = &set.stickers[0];
+        let entry = cache.get(first).await.unwrap();
+        assert_eq!(entry.file_unique_id, first.file_unique_id);
+        let dst = dir.path().join("out.webp");
+        std::fs::write(&dst, b"old").unwrap();
+        cache.place(&entry, &dst).await.unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), [0; 6]);
+        // Editing the output in place leaves the cache alone
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&dst)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, b"edited"))
+            .unwrap();
+        assert_eq!(std::fs::read(&entry.path).unwrap(), [0; 6]);
+        std::fs::write(&dst, [0; 6]).unwrap();
+        let modified = std::fs::metadata(&dst).unwrap().modified().unwrap();
+        cache.get(first).await.unwrap();
+        assert_eq!(
+            std::fs::metadata(&dst).unwrap().modified().unwrap(),
+            modified
+        );
+
+        let mut changed = first.clone();
+        changed.file_size = Some(7);
+        assert!(cache.get(&changed).await.is_none());
+        assert!(cache.get(&set.stickers[2]).await.is_none());
+
+        // The first sticker was used last, so the second one goes.
+        touch(
+            cache.path(&set.stickers[1].file_unique_id),
+            SystemTime::now() - Duration::from_secs(60),
+        )
+        .await;
+        let removed = cache.evict().await.unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].file_unique_id, set.stickers[1].file_unique_id);
+        assert!(cache.get(first).await.is_some());
+
+        assert_eq!(cache.clear().await.unwrap().len(), 1);
+        assert!(cache.entries().await.unwrap().is_empty());
+        assert!(dst.exists());
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex_encode("AgAD-_"), "416741442d5f");
+        assert_eq!(hex_decode(&hex_encode("AgAD-_")).as_deref(), Some("AgAD-_"));
+        assert_eq!(hex_decode(".41.part"), None);
+    }
+}
@@ -0,0 +1,458 @@
+//! Convert downloaded stickers into formats that other tools understand.
+//!
+//! Static stickers are handled in process by the `image` crate. Video stickers are
+//! converted by `ffmpeg`, and TGS animations are first rendered to GIF by
+//! `lottie_convert.py` (from python-lottie), so both need to be available in `PATH`.
+
+use std::{
+    fmt,
+    io::Cursor,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use image::{imageops::FilterType, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use tokio::process::Command;
+
+use crate::{
+    bot::StickerFileExt,
+    error::{Error, Result},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Gif,
+    Apng,
+    Webp,
+    Mp4,
+}
+
+impl OutputFormat {
+    pub fn ext(&self) -> &'static str {
+        match self {
+            OutputFormat::Png | OutputFormat::Apng => "png",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Mp4 => "mp4",
+        }
+    }
+
+    /// Static stickers have a single frame, so animation-only formats fall back to PNG.
+    fn for_static(self) -> Self {
+        match self {
+            OutputFormat::Apng | OutputFormat::Mp4 => OutputFormat::Png,
+            format => format,
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Apng => "apng",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Mp4 => "mp4",
+        })
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "gif" => Ok(OutputFormat::Gif),
+            "apng" => Ok(OutputFormat::Apng),
+            "webp" => Ok(OutputFormat::Webp),
+            "mp4" => Ok(OutputFormat::Mp4),
+            _ => Err(Error::ConvertError(format!("unknown format {}", s))),
+        }
+    }
+}
+
+/// An opaque RGB colour, parsed from `#RRGGBB` or `RRGGBB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub [u8; 3]);
+
+impl Color {
+    pub const WHITE: Color = Color([0xff, 0xff, 0xff]);
+
+    fn hex(&self) -> String {
+        format!("0x{:02x}{:02x}{:02x}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+impl FromStr for Color {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s.trim_start_matches('#');
+        let invalid = || Error::ConvertError(format!("invalid colour {}, expect #RRGGBB", s));
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        Ok(Color([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertOptions {
+    pub format: OutputFormat,
+    /// Fill transparent pixels, transparency is kept if `None` and the format supports it.
+    pub background: Option<Color>,
+    /// Fit the sticker into a `size`x`size` square, keeping the aspect ratio.
+    pub size: Option<u32>,
+    /// Pad the sticker to exactly `size`x`size`, which some platforms require.
+    pub square: bool,
+    /// Shrink the sticker further into a `content_size` square, still padded to `size`
+    /// when `square`, to make the file smaller.
+    pub content_size: Option<u32>,
+    /// Drop frames of animations above this frame rate.
+    pub fps: Option<u32>,
+    /// Quality of animated webp from 0 to 100, the default of libwebp if `None`.
+    pub quality: Option<u8>,
+}
+
+impl ConvertOptions {
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            background: None,
+            size: None,
+            square: false,
+            content_size: None,
+            fps: None,
+            quality: None,
+        }
+    }
+
+    fn content_size(&self, size: u32) -> u32 {
+        self.content_size.unwrap_or(size).min(size)
+    }
+
+    /// Whether the pixels of the sticker are changed, not only its format.
+    fn transforms(&self) -> bool {
+        self.background.is_some() || self.size.is_some()
+    }
+
+    /// Where `src` of kind `ext` is converted to.
+    ///
+    /// A static sticker converted to webp is `src` itself, unless it is resized or given a
+    /// background, which goes to `<name>.converted.webp` instead.
+    pub fn output_path(&self, src: &Path, ext: StickerFileExt) -> PathBuf {
+        let format = match ext {
+            StickerFileExt::Webp => self.format.for_static(),
+            _ => self.format,
+        };
+        let dst = src.with_extension(format.ext());
+        if dst == src && self.transforms() {
+            src.with_extension(format!("converted.{}", format.ext()))
+        } else {
+            dst
+        }
+    }
+}
+
+/// Convert the sticker file `src` of kind `ext`, returning the path of the new file.
+///
+/// The source file is left untouched, and returned as is when it is already in the
+/// requested format.
+pub async fn convert_file(
+    src: &Path,
+    ext: StickerFileExt,
+    options: &ConvertOptions,
+) -> Result<PathBuf> {
+    let dst = options.output_path(src, ext);
+    if dst != src {
+        convert_file_to(src, ext, &dst, options).await?;
+    }
+    Ok(dst)
+}
+
+/// Like [`convert_file`], but write the result to `dst`.
+pub async fn convert_file_to(
+    src: &Path,
+    ext: StickerFileExt,
+    dst: &Path,
+    options: &ConvertOptions,
+) -> Result<()> {
+    let dst = dst.to_owned();
+    match ext {
+        StickerFileExt::Webp => {
+            let data = tokio::fs::read(src).await?;
+            let options = options.clone();
+            let encoded = tokio::task::spawn_blocking(move || convert_static(&data, &options))
+                .await
+                .map_err(|e| Error::ConvertError(e.to_string()))??;
+            tokio::fs::write(&dst, encoded).await?;
+        }
+        StickerFileExt::Webm => {
+            run(Command::new("ffmpeg").args(ffmpeg_args(src, &dst, true, options))).await?;
+        }
+        StickerFileExt::Tgs => {
+            let gif = src.with_extension("render.gif");
+            run(Command::new("lottie_convert.py").arg(src).arg(&gif)).await?;
+            let res = if options.format == OutputFormat::Gif
+                && options.background.is_none()
+                && options.size.is_none()
+                && !options.square
+            {
+                tokio::fs::rename(&gif, &dst).await.map_err(Error::from)
+            } else {
+                run(Command::new("ffmpeg").args(ffmpeg_args(&gif, &dst, false, options))).await
+            };
+            tokio::fs::remove_file(&gif).await.ok();
+            res?;
+        }
+    }
+    Ok(())
+}
+
+fn convert_static(data: &[u8], options: &ConvertOptions) -> Result<Vec<u8>> {
+    let mut img = image::load_from_memory_with_format(data, ImageFormat::WebP)?;
+    if let Some(size) = options.size {
+        let content_size = options.content_size(size);
+        img = img.resize(content_size, content_size, FilterType::Lanczos3);
+        if options.square && (img.width(), img.height()) != (size, size) {
+            let mut canvas = RgbaImage::new(size, size);
+            let x = (size - img.width()) / 2;
+            let y = (size - img.height()) / 2;
+            image::imageops::overlay(&mut canvas, &img, x.into(), y.into());
+            img = DynamicImage::ImageRgba8(canvas);
+        }
+    }
+    if let Some(Color([r, g, b])) = options.background {
+        let mut bg = RgbaImage::from_pixel(img.width(), img.height(), Rgba([r, g, b, 0xff]));
+        image::imageops::overlay(&mut bg, &img, 0, 0);
+        img = DynamicImage::ImageRgba8(bg);
+    }
+    let format = match options.format.for_static() {
+        OutputFormat::Gif => ImageFormat::Gif,
+        OutputFormat::Webp => ImageFormat::WebP,
+        _ => ImageFormat::Png,
+    };
+    let mut encoded = Cursor::new(Vec::new());
+    img.to_rgba8().write_to(&mut encoded, format)?;
+    Ok(encoded.into_inner())
+}
+
+/// Arguments of ffmpeg converting the animation `src` to `dst`.
+///
+/// `vp9_alpha` selects the libvpx decoder, since the native VP9 decoder of ffmpeg
+/// drops the alpha channel of video stickers.
+pub fn ffmpeg_args(
+    src: &Path,
+    dst: &Path,
+    vp9_alpha: bool,
+    options: &ConvertOptions,
+) -> Vec<String> {
+    let mut args: Vec<String> = vec!["-y".into(), "-loglevel".into(), "error".into()];
+    if vp9_alpha {
+        args.extend(["-c:v".into(), "libvpx-vp9".into()]);
+    }
+    args.extend(["-i".into(), src.to_string_lossy().into_owned()]);
+
+    let mut filters = vec!["format=rgba".to_owned()];
+    if let Some(fps) = options.fps {
+        filters.push(format!("fps={}", fps));
+    }
+    if let Some(size) = options.size {
+        filters.push(format!(
+            "scale={0}:{0}:force_original_aspect_ratio=decrease",
+            options.content_size(size)
+        ));
+        if options.square {
+            filters.push(format!(
+                "pad={0}:{0}:(ow-iw)/2:(oh-ih)/2:color=0x00000000",
+                size
+            ));
+        }
+    }
+    // mp4 has no alpha channel, transparent pixels would become black
+    let background = match options.format {
+        OutputFormat::Mp4 => Some(options.background.unwrap_or(Color::WHITE)),
+        _ => options.background,
+    };
+    let mut graph = format!("[0:v]{}", filters.join(","));
+    if let Some(color) = background {
+        graph.push_str(&format!(
+            ",split[fg][bg];[bg]drawbox=x=0:y=0:w=iw:h=ih:color={}:t=fill[bgc];[bgc][fg]overlay",
+            color.hex()
+        ));
+    }
+
+    match options.format {
+        OutputFormat::Gif => {
+            graph.push_str(
+                ",split[s0][s1];[s0]palettegen=reserve_transparent=1[p];[s1][p]paletteuse=alpha_threshold=128",
+            );
+            args.extend(["-filter_complex".into(), graph, "-loop".into(), "0".into()]);
+        }
+        OutputFormat::Mp4 => {
+            graph.push_str(",scale=trunc(iw/2)*2:trunc(ih/2)*2,format=yuv420p");
+            args.extend([
+                "-filter_complex".into(),
+                graph,
+                "-c:v".into(),
+                "libx264".into(),
+                "-movflags".into(),
+                "+faststart".into(),
+            ]);
+        }
+        OutputFormat::Apng => {
+            args.extend([
+                "-filter_complex".into(),
+                graph,
+                "-plays".into(),
+                "0".into(),
+                "-f".into(),
+                "apng".into(),
+            ]);
+        }
+        OutputFormat::Webp => {
+            args.extend([
+                "-filter_complex".into(),
+                graph,
+                "-c:v".into(),
+                "libwebp".into(),
+                "-loop".into(),
+                "0".into(),
+            ]);
+            if let Some(quality) = options.quality {
+                args.extend(["-quality".into(), quality.to_string()]);
+            }
+        }
+        OutputFormat::Png => {
+            args.extend([
+                "-filter_complex".into(),
+                graph,
+                "-frames:v".into(),
+                "1".into(),
+            ]);
+        }
+    }
+    args.push(dst.to_string_lossy().into_owned());
+    args
+}
+
+async fn run(command: &mut Command) -> Result<()> {
+    let program = command
+        .as_std()
+        .get_program()
+        .to_string_lossy()
+        .into_owned();
+    let output = command.output().await.map_err(|e| {
+        Error::ConvertError(format!("fail to run {}, is it installed? {}", program, e))
+    })?;
+    if !output.status.success() {
+        return Err(Error::ConvertError(format!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn webp(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbaImage::from_pixel(width, height, Rgba([0x10, 0x20, 0x30, 0x00]));
+        let mut encoded = Cursor::new(Vec::new());
+        img.write_to(&mut encoded, ImageFormat::WebP).unwrap();
+        encoded.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_convert_static() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("sticker.webp");
+        std::fs::write(&src, webp(512, 256)).unwrap();
+
+        let options = ConvertOptions {
+            background: Some("#ff0000".parse().unwrap()),
+            size: Some(128),
+            ..ConvertOptions::new(OutputFormat::Mp4)
+        };
+        let dst = convert_file(&src, StickerFileExt::Webp, &options)
+            .await
+            .unwrap();
+        assert_eq!(dst, dir.path().join("sticker.png"));
+
+        let img = image::open(&dst).unwrap().to_rgba8();
+        assert_eq!(img.dimensions(), (128, 64));
+        assert_eq!(img.get_pixel(0, 0), &Rgba([0xff, 0, 0, 0xff]));
+        assert!(src.exists());
+
+        let webp = ConvertOptions::new(OutputFormat::Webp);
+        let dst = convert_file(&src, StickerFileExt::Webp, &webp)
+            .await
+            .unwrap();
+        assert_eq!(dst, src);
+        let resized = ConvertOptions {
+            size: Some(128),
+            ..webp
+        };
+        let dst = convert_file(&src, StickerFileExt::Webp, &resized)
+            .await
+            .unwrap();
+        assert_eq!(dst, dir.path().join("sticker.converted.webp"));
+        let img = image::open(&dst).unwrap();
+        assert_eq!((img.width(), img.height()), (128, 64));
+        let img = image::open(&src).unwrap();
+        assert_eq!((img.width(), img.height()), (512, 256));
+    }
+
+    #[test]
+    fn test_ffmpeg_args() {
+        let options = ConvertOptions {
+            size: Some(100),
+            ..ConvertOptions::new(OutputFormat::Mp4)
+        };
+        let args = ffmpeg_args(Path::new("a.webm"), Path::new("a.mp4"), true, &options);
+        assert_eq!(&args[3..7], ["-c:v", "libvpx-vp9", "-i", "a.webm"]);
+        let graph = &args[args.iter().position(|a| a == "-filter_complex").unwrap() + 1];
+        assert!(graph.contains("scale=100:100:force_original_aspect_ratio=decrease"));
+        assert!(graph.contains("color=0xffffff"));
+        assert_eq!(args.last().unwrap(), "a.mp4");
+
+        let args = ffmpeg_args(
+            Path::new("a.gif"),
+            Path::new("b.gif"),
+            false,
+            &ConvertOptions::new(OutputFormat::Gif),
+        );
+        assert!(!args.contains(&"libvpx-vp9".to_owned()));
+        assert!(args.iter().any(|a| a.contains("palettegen")));
+
+        let options = ConvertOptions {
+            size: Some(512),
+            square: true,
+            content_size: Some(256),
+            fps: Some(15),
+            quality: Some(30),
+            ..ConvertOptions::new(OutputFormat::Webp)
+        };
+        let args = ffmpeg_args(Path::new("a.webm"), Path::new("a.webp"), true, &options);
+        let graph = &args[args.iter().position(|a| a == "-filter_complex").unwrap() + 1];
+        assert!(graph.contains("fps=15,scale=256:256"));
+        assert!(graph.contains("pad=512:512"));
+        assert_eq!(&args[args.len() - 3..], ["-quality", "30", "a.webp"]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("APNG".parse::<OutputFormat>().unwrap(), OutputFormat::Apng);
+        assert!("jpeg".parse::<OutputFormat>().is_err());
+        assert_eq!("#0a0B0c".parse::<Color>().unwrap(), Color([10, 11, 12]));
+        assert!("#fff".parse::<Color>().is_err());
+    }
+}
@@ -0,0 +1,557 @@
+//! Download sticker sets to the local filesystem.
+//!
+//! [`Downloader::run`] drives the whole pipeline in a background task and reports
+//! what happens through a stream of [`DownloadEvent`]s, leaving the rendering to callers.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures_util::{stream, Stream, StreamExt};
+use log::warn;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, Semaphore},
+    task::JoinHandle,
+};
+
+use crate::{
+    bot::{Bot, Sticker, StickerFileExt, StickerSet, TelegramFileID},
+    cache::StickerCache,
+    error::{Error, Result},
+    manifest::Manifest,
+    pack::Pack,
+    sanitize::{Sanitizer, SetFolder},
+    template::{self, NameTemplate},
+};
+
+/// Build the file name of the `index`-th sticker of a set.
+pub type NamingFn = Arc<dyn Fn(&StickerSet, usize, &Sticker) -> String + Send + Sync>;
+
+/// What to do with stickers that may already exist in the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Skip stickers recorded in the manifest whose file is still intact.
+    #[default]
+    Sync,
+    /// Download every sticker again.
+    Overwrite,
+    /// Never replace a file that exists, even if it is not in the manifest.
+    KeepExisting,
+}
+
+pub const DEFAULT_JOBS: usize = 8;
+
+#[derive(Clone)]
+pub struct DownloadOptions {
+    pub destination: PathBuf,
+    /// Maximum number of stickers processed at the same time across all sets.
+    /// Each sticker holds its slot while resolving its file path and downloading it.
+    pub jobs: usize,
+    /// Maximum number of stickers of one set processed at the same time.
+    pub jobs_per_set: Option<usize>,
+    /// File names are passed through [`DownloadOptions::sanitizer`], `/` creates
+    /// directories inside the set directory.
+    pub naming: NamingFn,
+    /// Used instead of [`DownloadOptions::naming`] when set, and recorded in the manifest.
+    pub name_template: Option<NameTemplate>,
+    pub folder: SetFolder,
+    pub sanitizer: Sanitizer,
+    /// Directories of sets downloaded before, keyed by set name, used instead of the
+    /// one named after [`DownloadOptions::folder`] so that a set is synced in place even
+    /// if its title or the naming options changed.
+    pub set_dirs: HashMap<String, PathBuf>,
+    /// Keep naming the stickers of a set downloaded before with the options recorded
+    /// in its manifest, i.e. [`Sanitizer::ascii`] and the name template unless one is
+    /// given here.
+    pub recorded_naming: bool,
+    pub overwrite: OverwritePolicy,
+    /// Remove local stickers that were deleted from the set.
+    pub prune: bool,
+    /// Write `pack.csv` along with `pack.json`, see [`crate::pack`].
+    pub pack_csv: bool,
+    /// Check that downloaded files start with the signature of their format.
+    /// Their size is always checked when Telegram reports it.
+    pub check_format: bool,
+    /// Reuse stickers downloaded into other sets or by earlier runs.
+    pub cache: Option<StickerCache>,
+}
+
+impl DownloadOptions {
+    pub fn new(destination: PathBuf) -> Self {
+        Self {
+            destination,
+            jobs: DEFAULT_JOBS,
+            jobs_per_set: None,
+            naming: Arc::new(|_, _, sticker| default_file_name(sticker)),
+            name_template: None,
+            folder: SetFolder::default(),
+            sanitizer: Sanitizer::default(),
+            set_dirs: HashMap::new(),
+            recorded_naming: false,
+            overwrite: OverwritePolicy::default(),
+            prune: false,
+            pack_csv: false,
+            check_format: false,
+            cache: None,
+        }
+    }
+
+    /// Directory the stickers of `set` are downloaded to.
+    pub fn set_dir(&self, set: &StickerSet) -> PathBuf {
+        match self.set_dirs.get(&set.name) {
+            Some(dir) => dir.clone(),
+            None => self
+                .destination
+                .join(self.sanitizer.set_dir_name(set, self.folder)),
+        }
+    }
+}
+
+/// `<emoji_name>_<file_id>.<ext>`
+pub fn default_file_name(sticker: &Sticker) -> String {
+    format!(
+        "{}_{}.{}",
+        template::emoji_name(sticker),
+        sticker.file_id,
+        sticker.file_ext()
+    )
+}
+
+#[derive(Debug)]
+pub enum DownloadEvent {
+    SetStarted {
+        set: String,
+        title: String,
+        dir: PathBuf,
+        total: usize,
+    },
+    StickerSkipped {
+        set: String,
+        sticker: Box<Sticker>,
+        path: PathBuf,
+    },
+    StickerDownloaded {
+        set: String,
+        sticker: Box<Sticker>,
+        path: PathBuf,
+        size: u64,
+        /// Taken from [`DownloadOptions::cache`] instead of Telegram.
+        cached: bool,
+    },
+    StickerFailed {
+        set: String,
+        sticker: Box<Sticker>,
+        error: Error,
+    },
+    StickersPruned {
+        set: String,
+        paths: Vec<PathBuf>,
+    },
+    /// The set could not be processed at all, e.g. its directory is not writable.
+    SetFailed {
+        set: String,
+        error: Error,
+    },
+    SetFinished {
+        set: String,
+        downloaded: usize,
+        skipped: usize,
+        failed: usize,
+    },
+}
+
+pub struct Downloader {
+    bot: Bot,
+    sticker_sets: Vec<StickerSet>,
+    options: DownloadOptions,
+}
+
+impl Downloader {
+    pub fn new(bot: Bot, sticker_sets: Vec<StickerSet>, options: DownloadOptions) -> Self {
+        Self {
+            bot,
+            sticker_sets,
+            options,
+        }
+    }
+
+    /// Start downloading. Dropping the returned stream cancels the download.
+    pub fn run(self) -> DownloadStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let handle = tokio::spawn(async move {
+            let jobs = Semaphore::new(self.options.jobs.max(1));
+            stream::iter(self.sticker_sets.iter())
+                .for_each_concurrent(None, |set| {
+                    download_set(&self.bot, set, &self.options, &jobs, &tx)
+                })
+                .await;
+            if let Some(cache) = &self.options.cache {
+                if let Err(err) = cache.evict().await {
+                    warn!("fail to evict stickers from cache: {}", err);
+                }
+            }
+        });
+        DownloadStream { rx, handle }
+    }
+}
+
+pub struct DownloadStream {
+    rx: mpsc::UnboundedReceiver<DownloadEvent>,
+    handle: JoinHandle<()>,
+}
+
+impl Stream for DownloadStream {
+    type Item = DownloadEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for DownloadStream {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+type Sender = mpsc::UnboundedSender<DownloadEvent>;
+
+async fn download_set(
+    bot: &Bot,
+    set: &StickerSet,
+    options: &DownloadOptions,
+    jobs: &Semaphore,
+    tx: &Sender,
+) {
+    let dir = options.set_dir(set);
+    let send = |event| {
+        tx.send(event).ok();
+    };
+    let set_failed = |error| {
+        send(DownloadEvent::SetFailed {
+            set: set.name.clone(),
+            error,
+        })
+    };
+    send(DownloadEvent::SetStarted {
+        set: set.name.clone(),
+        title: set.title.clone(),
+        dir: dir.clone(),
+        total: set.stickers.len(),
+    });
+
+    if let Err(err) = tokio::fs::create_dir_all(&dir).await {
+        return set_failed(Error::IoError(err));
+    }
+    let mut manifest = match Manifest::load(&dir).await {
+        Ok(manifest) => manifest,
+        Err(err) => return set_failed(err),
+    };
+    manifest.update_set(set);
+    let sanitizer = Sanitizer {
+        ascii: options.sanitizer.ascii || (options.recorded_naming && manifest.ascii),
+        ..options.sanitizer
+    };
+    manifest.ascii = sanitizer.ascii;
+    let name_template = match (&options.name_template, &manifest.name_template) {
+        (Some(template), _) => Some(template.clone()),
+        (None, Some(recorded)) if options.recorded_naming => match NameTemplate::parse(recorded) {
+            Ok(template) => Some(template),
+            Err(err) => return set_failed(err),
+        },
+        (None, _) => None,
+    };
+    manifest.name_template = name_template.as_ref().map(|template| template.to_string());
+    if options.prune {
+        match manifest.prune(&dir, set).await {
+            Ok(removed) if !removed.is_empty() => send(DownloadEvent::StickersPruned {
+                set: set.name.clone(),
+                paths: removed.into_iter().map(|e| dir.join(e.file_name)).collect(),
+            }),
+            Ok(_) => {}
+            Err(err) => return set_failed(err),
+        }
+    }
+
+    let file_names: Vec<String> = set
+        .stickers
+        .iter()
+        .enumerate()
+        .map(|(index, sticker)| {
+            let file_name = match &name_template {
+                Some(template) => template.render(set, index, sticker),
+                None => (options.naming)(set, index, sticker),
+            };
+            sanitizer.relative_path(&file_name)
+        })
+        .collect();
+    // Compared case-insensitively, as on the default filesystems of Windows and macOS.
+    let mut seen = HashSet::new();
+    if let Some(file_name) = file_names
+        .iter()
+        .find(|file_name| !seen.insert(file_name.to_lowercase()))
+    {
+        return set_failed(Error::NameCollision {
+            set: set.name.clone(),
+            file_name: file_name.clone(),
+        });
+    }
+
+    // Stickers downloaded under another name, e.g. before the name template changed,
+    // are renamed rather than downloaded again.
+    let mut renames = Vec::new();
+    if options.overwrite != OverwritePolicy::Overwrite {
+        for (sticker, file_name) in set.stickers.iter().zip(&file_names) {
+            let Some(entry) = manifest.stickers.get(&sticker.file_unique_id) else {
+                continue;
+            };
+            if &entry.file_name == file_name || !manifest.is_synced(&dir, sticker).await {
+                continue;
+            }
+            if options.overwrite == OverwritePolicy::KeepExisting
+                && tokio::fs::try_exists(dir.join(file_name))
+                    .await
+                    .unwrap_or(false)
+            {
+                continue;
+            }
+            renames.push((sticker.file_unique_id.clone(), file_name.clone()));
+        }
+    }
+    if let Err(err) = manifest.rename(&dir, &renames).await {
+        return set_failed(err);
+    }
+
+    let mut skipped = 0;
+    let mut pending = Vec::new();
+    for (sticker, file_name) in set.stickers.iter().zip(file_names) {
+        if should_skip(&manifest, &dir, sticker, &file_name, options.overwrite).await {
+            skipped += 1;
+            let path = manifest
+                .stickers
+                .get(&sticker.file_unique_id)
+                .map(|entry| dir.join(&entry.file_name))
+                .unwrap_or_else(|| dir.join(&file_name));
+            send(DownloadEvent::StickerSkipped {
+                set: set.name.clone(),
+                sticker: Box::new(sticker.clone()),
+                path,
+            });
+        } else {
+            pending.push((sticker, file_name));
+        }
+    }
+
+    let set_jobs = options.jobs_per_set.map(|n| Semaphore::new(n.max(1)));
+    let manifest = Mutex::new(manifest);
+    let downloaded = Mutex::new(0);
+    stream::iter(pending)
+        .for_each_concurrent(None, |(sticker, file_name)| {
+            let dir = &dir;
+            let manifest = &manifest;
+            let downloaded = &downloaded;
+            let set_jobs = &set_jobs;
+            async move {
+                let _set_permit = match set_jobs {
+                    Some(set_jobs) => Some(set_jobs.acquire().await.unwrap()),
+                    None => None,
+                };
+                let _permit = jobs.acquire().await.unwrap();
+                let path = dir.join(&file_name);
+                match fetch_sticker(bot, sticker, &path, options).await {
+                    Ok((size, cached)) => {
+                        manifest.lock().unwrap().record(sticker, file_name, size);
+                        *downloaded.lock().unwrap() += 1;
+                        send(DownloadEvent::StickerDownloaded {
+                            set: set.name.clone(),
+                            sticker: Box::new(sticker.clone()),
+                            path,
+                            size,
+                            cached,
+                        });
+                    }
+                    Err(error) => {
+                        send(DownloadEvent::StickerFailed {
+                            set: set.name.clone(),
+                            sticker: Box::new(sticker.clone()),
+                            error,
+                        });
+                    }
+                }
+            }
+        })
+        .await;
+
+    let manifest = manifest.into_inner().unwrap();
+    if let Err(err) = manifest.save(&dir).await {
+        return set_failed(err);
+    }
+    let pack = Pack::new(set, &manifest);
+    if let Err(err) = pack.save(&dir).await {
+        return set_failed(err);
+    }
+    if options.pack_csv {
+        if let Err(err) = pack.save_csv(&dir).await {
+            return set_failed(err);
+        }
+    }
+    let downloaded = downloaded.into_inner().unwrap();
+    send(DownloadEvent::SetFinished {
+        set: set.name.clone(),
+        downloaded,
+        skipped,
+        failed: set.stickers.len() - downloaded - skipped,
+    });
+}
+
+/// Download `sticker` to `path` or take it from the cache, returning its size and
+/// whether it was cached.
+async fn fetch_sticker(
+    bot: &Bot,
+    sticker: &Sticker,
+    path: &Path,
+    options: &DownloadOptions,
+) -> Result<(u64, bool)> {
+    if let Some(cache) = &options.cache {
+        if let Some(entry) = cache.get(sticker).await {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            match cache.place(&entry, path).await {
+                Ok(_) => return Ok((entry.size, true)),
+                Err(err) => warn!("fail to copy {} from cache: {}", path.display(), err),
+            }
+        }
+    }
+    let file_id = bot.request_file_id(sticker).await?;
+    let expected = Expected::sticker(sticker, options.check_format);
+    let size = download_verified(bot, &file_id, path, expected).await?;
+    if let Some(cache) = &options.cache {
+        if let Err(err) = cache.insert(sticker, path).await {
+            warn!("fail to cache {}: {}", path.display(), err);
+        }
+    }
+    Ok((size, false))
+}
+
+async fn should_skip(
+    manifest: &Manifest,
+    dir: &Path,
+    sticker: &Sticker,
+    file_name: &str,
+    overwrite: OverwritePolicy,
+) -> bool {
+    match overwrite {
+        OverwritePolicy::Overwrite => false,
+        OverwritePolicy::Sync => manifest.is_synced(dir, sticker).await,
+        OverwritePolicy::KeepExisting => {
+            manifest.is_synced(dir, sticker).await
+                || tokio::fs::try_exists(dir.join(file_name))
+                    .await
+                    .unwrap_or(false)
+        }
+    }
+}
+
+/// What a downloaded file is checked against before it replaces the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Expected {
+    pub size: Option<u64>,
+    /// Check the signature of the format.
+    pub ext: Option<StickerFileExt>,
+}
+
+impl Expected {
+    pub fn sticker(sticker: &Sticker, check_format: bool) -> Self {
+        Self {
+            size: sticker.file_size,
+            ext: check_format.then(|| sticker.file_ext()),
+        }
+    }
+}
+
+/// Stream a file to `dst`, returning the number of bytes written.
+///
+/// Like [`download_verified`] without any check.
+pub async fn download_sticker(bot: &Bot, file_id: &TelegramFileID, dst: &Path) -> Result<u64> {
+    download_verified(bot, file_id, dst, Expected::default()).await
+}
+
+/// Stream a file to a temporary file next to `dst`, and move it to `dst` once it is
+/// complete and matches `expected`, so `dst` is never left half written.
+pub async fn download_verified(
+    bot: &Bot,
+    file_id: &TelegramFileID,
+    dst: &Path,
+    expected: Expected,
+) -> Result<u64> {
+    if let Some(parent) = dst.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let file_name = dst.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dst.with_file_name(format!(".{}.part", file_name));
+    let res = write_verified(bot, file_id, &tmp, dst, expected).await;
+    if res.is_err() {
+        tokio::fs::remove_file(&tmp).await.ok();
+    }
+    res
+}
+
+async fn write_verified(
+    bot: &Bot,
+    file_id: &TelegramFileID,
+    tmp: &Path,
+    dst: &Path,
+    expected: Expected,
+) -> Result<u64> {
+    let mut bytes = bot.download_file(file_id).await?;
+    let mut file = tokio::fs::File::create(tmp).await?;
+    let mut head = Vec::with_capacity(SIGNATURE_LEN);
+    let mut written = 0;
+    while let Some(chunk) = bytes.next().await {
+        let chunk = chunk?;
+        if head.len() < SIGNATURE_LEN {
+            let n = (SIGNATURE_LEN - head.len()).min(chunk.len());
+            head.extend_from_slice(&chunk[..n]);
+        }
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    file.sync_all().await?;
+    drop(file);
+
+    if let Some(size) = expected.size.filter(|size| *size != written) {
+        return Err(Error::SizeMismatch {
+            path: dst.to_owned(),
+            expected: size,
+            actual: written,
+        });
+    }
+    if let Some(ext) = expected.ext.filter(|ext| !has_signature(*ext, &head)) {
+        return Err(Error::InvalidFileFormat {
+            path: dst.to_owned(),
+            ext,
+        });
+    }
+    tokio::fs::rename(tmp, dst).await?;
+    Ok(written)
+}
+
+const SIGNATURE_LEN: usize = 12;
+
+fn has_signature(ext: StickerFileExt, head: &[u8]) -> bool {
+    match ext {
+        StickerFileExt::Webp => {
+            head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP".as_slice())
+        }
+        // EBML header
+        StickerFileExt::Webm => head.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]),
+        // gzip
+        StickerFileExt::Tgs => head.starts_with(&[0x1f, 0x8b]),
+    }
+}
@@ -1,17 +1,121 @@
+use std::{path::PathBuf, time::Duration};
+
 use thiserror::Error;
 
+use crate::bot::StickerFileExt;
+
 #[derive(Debug, Error)]
-pub enum Error{
-    #[error("bot error: {0}")]
-    BotError(String),
+pub enum Error {
+    /// Telegram answered with `"ok": false`
+    #[error("telegram api error {code}: {description}")]
+    Api {
+        code: i32,
+        description: String,
+        retry_after: Option<Duration>,
+        migrate_to_chat_id: Option<i64>,
+    },
 
+    /// Without its URL, which contains the bot token.
     #[error(transparent)]
-    RequestError(#[from] reqwest::Error),
+    RequestError(reqwest::Error),
 
     #[error(transparent)]
-    ResponseJsonError(serde_json::Error)
+    ResponseJsonError(serde_json::Error),
+
+    #[error("invalid sticker link {link}: {reason}")]
+    InvalidLink { link: String, reason: String },
+
+    #[error("invalid name template {template}: {reason}")]
+    InvalidTemplate { template: String, reason: String },
+
+    /// Two stickers of `set` would be written to the same file.
+    #[error("stickers of {set} share the file name {file_name}, add {{index}} or {{unique_id}} to the name template")]
+    NameCollision { set: String, file_name: String },
+
+    /// A downloaded file is not as big as Telegram reported, it was discarded.
+    #[error("{} has {actual} bytes, expected {expected}", path.display())]
+    SizeMismatch {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+
+    /// A downloaded file does not start with the signature of its format, it was discarded.
+    #[error("{} is not a valid {ext} file", path.display())]
+    InvalidFileFormat { path: PathBuf, ext: StickerFileExt },
+
+    /// Adding the stickers of a new set failed, and so did deleting the set afterwards.
+    #[error("sticker set {name} was left with {added} stickers: {source}")]
+    IncompleteSet {
+        name: String,
+        added: usize,
+        source: Box<Error>,
+    },
+
+    #[error("invalid tgs file: {0}")]
+    TgsError(String),
+
+    #[error("fail to convert sticker: {0}")]
+    ConvertError(String),
 
+    #[cfg(feature = "convert")]
+    #[error(transparent)]
+    ImageError(#[from] image::ImageError),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
 }
 
+impl Error {
+    /// The sticker set or file does not exist.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::Api {
+                code, description, ..
+            } => {
+                *code == 404
+                    || (*code == 400
+                        && (description.contains("STICKERSET_INVALID")
+                            || description.contains("not found")
+                            || description.contains("invalid file_id")))
+            }
+            Error::RequestError(e) => e.status() == Some(reqwest::StatusCode::NOT_FOUND),
+            _ => false,
+        }
+    }
+
+    /// The bot token is invalid or revoked.
+    pub fn is_unauthorized(&self) -> bool {
+        match self {
+            Error::Api { code, .. } => *code == 401,
+            Error::RequestError(e) => e.status() == Some(reqwest::StatusCode::UNAUTHORIZED),
+            _ => false,
+        }
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            Error::Api {
+                code, retry_after, ..
+            } => *code == 429 || retry_after.is_some(),
+            Error::RequestError(e) => e.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS),
+            _ => false,
+        }
+    }
+
+    /// How long Telegram asked us to wait before the next request.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Api { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::RequestError(err.without_url())
+    }
+}
 
-pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,436 @@
+//! Export downloaded sticker sets into the layouts other messengers import.
+//!
+//! Exporters only read the files produced by [`crate::download`], converting them
+//! with [`crate::convert`] where the target platform needs another format or size.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    bot::{Sticker, StickerFileExt, StickerSet, FALLBACK_EMOJI},
+    convert::{convert_file_to, ConvertOptions, OutputFormat},
+    error::{Error, Result},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportTarget {
+    Signal,
+    Whatsapp,
+    Discord,
+}
+
+impl fmt::Display for ExportTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExportTarget::Signal => "signal",
+            ExportTarget::Whatsapp => "whatsapp",
+            ExportTarget::Discord => "discord",
+        })
+    }
+}
+
+impl FromStr for ExportTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "signal" => Ok(ExportTarget::Signal),
+            "whatsapp" => Ok(ExportTarget::Whatsapp),
+            "discord" => Ok(ExportTarget::Discord),
+            _ => Err(Error::ConvertError(format!("unknown export target {}", s))),
+        }
+    }
+}
+
+/// Limits of sticker files on each platform.
+struct Limits {
+    size: u32,
+    static_bytes: u64,
+    animated_bytes: u64,
+    per_pack: usize,
+}
+
+impl ExportTarget {
+    fn limits(&self) -> Limits {
+        match self {
+            ExportTarget::Signal => Limits {
+                size: 512,
+                static_bytes: 300 * 1024,
+                animated_bytes: 300 * 1024,
+                per_pack: 200,
+            },
+            ExportTarget::Whatsapp => Limits {
+                size: 512,
+                static_bytes: 100 * 1024,
+                animated_bytes: 500 * 1024,
+                per_pack: 30,
+            },
+            ExportTarget::Discord => Limits {
+                size: 320,
+                static_bytes: 512 * 1024,
+                animated_bytes: 512 * 1024,
+                per_pack: usize::MAX,
+            },
+        }
+    }
+
+    /// Options tried in turn until the file of a sticker of kind `ext` fits in the size
+    /// limit: as is, then smaller, with fewer frames and a lower quality for animations.
+    fn attempts(&self, ext: StickerFileExt) -> Vec<ConvertOptions> {
+        let size = self.limits().size;
+        let base = ConvertOptions {
+            size: Some(size),
+            square: *self != ExportTarget::Discord,
+            ..ConvertOptions::new(self.format(ext))
+        };
+        let mut attempts = vec![base.clone()];
+        for (percent, fps, quality) in [(100, 24, 60), (85, 20, 45), (70, 15, 30), (50, 10, 20)] {
+            let mut options = ConvertOptions {
+                content_size: (percent < 100).then_some(size * percent / 100),
+                ..base.clone()
+            };
+            if ext != StickerFileExt::Webp {
+                options.fps = Some(fps);
+                options.quality = Some(quality);
+            }
+            if !attempts.contains(&options) {
+                attempts.push(options);
+            }
+        }
+        attempts
+    }
+
+    fn format(&self, ext: StickerFileExt) -> OutputFormat {
+        match (self, ext) {
+            (ExportTarget::Signal | ExportTarget::Whatsapp, StickerFileExt::Webp) => {
+                OutputFormat::Webp
+            }
+            (ExportTarget::Signal, _) => OutputFormat::Apng,
+            (ExportTarget::Whatsapp, _) => OutputFormat::Webp,
+            (ExportTarget::Discord, StickerFileExt::Webp) => OutputFormat::Png,
+            (ExportTarget::Discord, _) => OutputFormat::Apng,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportReport {
+    pub dir: PathBuf,
+    pub exported: usize,
+    /// Downloaded files of stickers still too big for the platform once shrunk, which
+    /// were left out.
+    pub left_out: Vec<PathBuf>,
+    /// Platform limits the exported packs still break, the import may reject them.
+    pub warnings: Vec<String>,
+}
+
+/// Export the downloaded `files` of `set`, in the order of the set, into `dir`.
+///
+/// Stickers are shrunk until their files fit in the limits of `target`, or left out.
+pub async fn export_set(
+    target: ExportTarget,
+    set: &StickerSet,
+    files: &[(Sticker, PathBuf)],
+    dir: &Path,
+) -> Result<ExportReport> {
+    tokio::fs::create_dir_all(dir).await?;
+    let mut report = ExportReport {
+        dir: dir.to_owned(),
+        exported: 0,
+        left_out: Vec::new(),
+        warnings: Vec::new(),
+    };
+    let limits = target.limits();
+    let mut exported = Vec::with_capacity(files.len());
+    for (index, (sticker, src)) in files.iter().enumerate() {
+        let ext = sticker.file_ext();
+        let limit = match ext {
+            StickerFileExt::Webp => limits.static_bytes,
+            _ => limits.animated_bytes,
+        };
+        let file_name = format!("{:03}.{}", index, target.format(ext).ext());
+        let dst = dir.join(&file_name);
+        let mut fits = false;
+        for options in target.attempts(ext) {
+            convert_file_to(src, ext, &dst, &options).await?;
+            if tokio::fs::metadata(&dst).await?.len() <= limit {
+                fits = true;
+                break;
+            }
+        }
+        if !fits {
+            tokio::fs::remove_file(&dst).await?;
+            report.left_out.push(src.clone());
+            continue;
+        }
+        exported.push(ExportedSticker {
+            file: file_name,
+            emoji: sticker
+                .emoji
+                .clone()
+                .unwrap_or_else(|| FALLBACK_EMOJI.to_owned()),
+            src: src.clone(),
+            ext,
+        });
+    }
+    report.exported = exported.len();
+    if exported.len() > limits.per_pack && target != ExportTarget::Whatsapp {
+        report.warnings.push(format!(
+            "{} allows at most {} stickers in a pack, but {} has {}",
+            target,
+            limits.per_pack,
+            set.name,
+            exported.len()
+        ));
+    }
+
+    match target {
+        ExportTarget::Signal => write_signal_manifest(set, &exported, dir).await?,
+        ExportTarget::Whatsapp => {
+            write_whatsapp_contents(set, &exported, dir, &mut report.warnings).await?
+        }
+        ExportTarget::Discord => {}
+    }
+    Ok(report)
+}
+
+struct ExportedSticker {
+    file: String,
+    emoji: String,
+    src: PathBuf,
+    ext: StickerFileExt,
+}
+
+impl ExportedSticker {
+    fn is_animated(&self) -> bool {
+        self.ext != StickerFileExt::Webp
+    }
+}
+
+#[derive(Serialize)]
+struct SignalSticker<'a> {
+    file: &'a str,
+    emoji: &'a str,
+}
+
+async fn write_signal_manifest(
+    set: &StickerSet,
+    stickers: &[ExportedSticker],
+    dir: &Path,
+) -> Result<()> {
+    let entries: Vec<SignalSticker> = stickers
+        .iter()
+        .map(|s| SignalSticker {
+            file: &s.file,
+            emoji: &s.emoji,
+        })
+        .collect();
+    let manifest = json!({
+        "title": set.title,
+        "author": "",
+        "cover": entries.first(),
+        "stickers": entries,
+    });
+    write_json(&dir.join("manifest.json"), &manifest).await
+}
+
+/// WhatsApp packs hold 3 to 30 stickers and never mix static and animated ones,
+/// so a set becomes several packs in the same `contents.json`.
+async fn write_whatsapp_contents(
+    set: &StickerSet,
+    stickers: &[ExportedSticker],
+    dir: &Path,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let per_pack = ExportTarget::Whatsapp.limits().per_pack;
+    let mut packs = Vec::new();
+    for animated in [false, true] {
+        let group: Vec<&ExportedSticker> = stickers
+            .iter()
+            .filter(|s| s.is_animated() == animated)
+            .collect();
+        for chunk in group.chunks(per_pack) {
+            let identifier = format!("{}_{}", set.name, packs.len() + 1);
+            if chunk.len() < 3 {
+                warnings.push(format!(
+                    "whatsapp pack {} has {} stickers, at least 3 are required",
+                    identifier,
+                    chunk.len()
+                ));
+            }
+
+            let tray = format!("tray_{}.png", packs.len() + 1);
+            let options = ConvertOptions {
+                size: Some(96),
+                square: true,
+                ..ConvertOptions::new(OutputFormat::Png)
+            };
+            convert_file_to(&chunk[0].src, chunk[0].ext, &dir.join(&tray), &options).await?;
+
+            let name = if packs.is_empty() {
+                set.title.clone()
+            } else {
+                format!("{} {}", set.title, packs.len() + 1)
+            };
+            packs.push(json!({
+                "identifier": identifier,
+                "name": name,
+                "publisher": "",
+                "tray_image_file": tray,
+                "image_data_version": "1",
+                "avoid_cache": false,
+                "publisher_email": "",
+                "publisher_website": "",
+                "privacy_policy_website": "",
+                "license_agreement_website": "",
+                "animated_sticker_pack": animated,
+                "stickers": chunk
+                    .iter()
+                    .map(|s| json!({ "image_file": s.file, "emojis": [s.emoji] }))
+                    .collect::<Vec<_>>(),
+            }));
+        }
+    }
+    let contents = json!({
+        "android_play_store_link": "",
+        "ios_app_store_link": "",
+        "sticker_packs": packs,
+    });
+    write_json(&dir.join("contents.json"), &contents).await
+}
+
+async fn write_json(path: &Path, value: &serde_json::Value) -> Result<()> {
+    let content = serde_json::to_vec_pretty(value).map_err(Error::ResponseJsonError)?;
+    tokio::fs::write(path, content).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use image::{ImageFormat, RgbaImage};
+
+    use super::*;
+
+    fn sticker_set() -> StickerSet {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/static_set.json"
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn read_json(path: PathBuf) -> serde_json::Value {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let set = sticker_set();
+        let files: Vec<(Sticker, PathBuf)> = set
+            .stickers
+            .iter()
+            .enumerate()
+            .map(|(idx, sticker)| {
+                let path = dir.path().join(format!("{}.webp", idx));
+                RgbaImage::new(512, 256)
+                    .save_with_format(&path, ImageFormat::WebP)
+                    .unwrap();
+                (sticker.clone(), path)
+            })
+            .collect();
+
+        let out = dir.path().join("whatsapp");
+        let report = export_set(ExportTarget::Whatsapp, &set, &files[..2], &out)
+            .await
+            .unwrap();
+        assert_eq!(report.exported, 2);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(
+            image::image_dimensions(out.join("001.webp")).unwrap(),
+            (512, 512)
+        );
+        assert_eq!(
+            image::image_dimensions(out.join("tray_1.png")).unwrap(),
+            (96, 96)
+        );
+        let contents = read_json(out.join("contents.json"));
+        let pack = &contents["sticker_packs"][0];
+        assert_eq!(pack["name"], set.title);
+        assert_eq!(
+            pack["stickers"][1]["emojis"][0],
+            set.stickers[1].emoji.as_deref().unwrap()
+        );
+
+        let out = dir.path().join("signal");
+        export_set(ExportTarget::Signal, &set, &files, &out)
+            .await
+            .unwrap();
+        let manifest = read_json(out.join("manifest.json"));
+        assert_eq!(manifest["stickers"].as_array().unwrap().len(), 4);
+        assert_eq!(manifest["cover"]["file"], "000.webp");
+
+        let out = dir.path().join("discord");
+        let report = export_set(ExportTarget::Discord, &set, &files, &out)
+            .await
+            .unwrap();
+        assert!(report.warnings.is_empty());
+        assert_eq!(
+            image::image_dimensions(out.join("003.png")).unwrap(),
+            (320, 160)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let set = sticker_set();
+        // Noise barely compresses, around 4 bytes per pixel
+        let mut seed: u32 = 1;
+        let noise = RgbaImage::from_fn(512, 512, |_, _| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            image::Rgba(seed.to_le_bytes())
+        });
+        let path = dir.path().join("noise.webp");
+        noise.save_with_format(&path, ImageFormat::WebP).unwrap();
+        let files = vec![(set.stickers[0].clone(), path.clone())];
+
+        let out = dir.path().join("signal");
+        let report = export_set(ExportTarget::Signal, &set, &files, &out)
+            .await
+            .unwrap();
+        assert_eq!(report.exported, 1);
+        let len = std::fs::metadata(out.join("000.webp")).unwrap().len();
+        assert!(len <= 300 * 1024);
+        assert_eq!(
+            image::image_dimensions(out.join("000.webp")).unwrap(),
+            (512, 512)
+        );
+
+        let out = dir.path().join("whatsapp");
+        let report = export_set(ExportTarget::Whatsapp, &set, &files, &out)
+            .await
+            .unwrap();
+        assert_eq!(report.exported, 0);
+        assert_eq!(report.left_out, [path]);
+        assert!(!out.join("000.webp").exists());
+    }
+
+    #[test]
+    fn test_attempts() {
+        let attempts = ExportTarget::Whatsapp.attempts(StickerFileExt::Webm);
+        assert_eq!(attempts.len(), 5);
+        assert_eq!(attempts[0].fps, None);
+        assert_eq!(attempts[4].content_size, Some(256));
+        assert_eq!(attempts[4].quality, Some(20));
+        let attempts = ExportTarget::Whatsapp.attempts(StickerFileExt::Webp);
+        assert_eq!(attempts.len(), 4);
+        assert!(attempts.iter().all(|options| options.fps.is_none()));
+    }
+}
@@ -1,2 +1,19 @@
+pub mod api_cache;
+pub mod bot;
+pub mod cache;
+#[cfg(feature = "convert")]
+pub mod convert;
+pub mod download;
 pub mod error;
-pub mod bot;
\ No newline at end of file
+#[cfg(feature = "convert")]
+pub mod export;
+pub mod link;
+pub mod manifest;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod pack;
+pub mod retry;
+pub mod sanitize;
+pub mod template;
+pub mod tgs;
+pub mod upload;
@@ -0,0 +1,187 @@
+//! Parse the links Telegram shares for sticker sets, emoji sets and custom emoji.
+
+use std::fmt;
+
+use reqwest::Url;
+
+use crate::error::{Error, Result};
+
+const HOSTS: [&str; 3] = ["t.me", "telegram.me", "telegram.dog"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StickerLink {
+    /// `t.me/addstickers/<name>`, or a bare set name.
+    StickerSet(String),
+    /// `t.me/addemoji/<name>`
+    EmojiSet(String),
+    /// `custom_emoji_id` of a single custom emoji.
+    CustomEmoji(String),
+}
+
+impl StickerLink {
+    /// Accepts
+    ///
+    /// - `https://t.me/addstickers/<name>`, also through `telegram.me` and `telegram.dog`,
+    ///   with or without scheme, query string or trailing slash
+    /// - `https://t.me/addemoji/<name>`
+    /// - `tg://addstickers?set=<name>` and `tg://addemoji?set=<name>`
+    /// - a bare set name, or a numeric custom emoji id
+    pub fn parse(link: &str) -> Result<Self> {
+        let link = link.trim();
+        let invalid = |reason: &str| Error::InvalidLink {
+            link: link.to_owned(),
+            reason: reason.to_owned(),
+        };
+        if link.is_empty() {
+            return Err(invalid("link is empty"));
+        }
+        if link.bytes().all(|b| b.is_ascii_digit()) {
+            return match link.parse::<u64>() {
+                Ok(_) => Ok(StickerLink::CustomEmoji(link.to_owned())),
+                Err(_) => Err(invalid("custom emoji id is too large")),
+            };
+        }
+        if !link.contains(['/', ':', '?']) {
+            return Ok(StickerLink::StickerSet(validate_set_name(link)?));
+        }
+
+        let url = if link.contains("://") {
+            Url::parse(link)
+        } else {
+            Url::parse(&format!("https://{}", link))
+        }
+        .map_err(|e| invalid(&e.to_string()))?;
+
+        let (kind, name) = match url.scheme() {
+            "tg" => {
+                let name = url
+                    .query_pairs()
+                    .find(|(key, _)| key == "set")
+                    .map(|(_, name)| name.into_owned());
+                (url.host_str().unwrap_or_default().to_owned(), name)
+            }
+            "http" | "https" => {
+                let host = url.host_str().unwrap_or_default();
+                if !HOSTS.contains(&host.trim_start_matches("www.")) {
+                    return Err(invalid("not a telegram link"));
+                }
+                let mut segments = url
+                    .path_segments()
+                    .into_iter()
+                    .flatten()
+                    .filter(|s| !s.is_empty());
+                let kind = segments.next().unwrap_or_default().to_owned();
+                let name = segments.next().map(str::to_owned);
+                if segments.next().is_some() {
+                    return Err(invalid("unexpected path after the set name"));
+                }
+                (kind, name)
+            }
+            _ => return Err(invalid("unsupported scheme")),
+        };
+        let name = name.ok_or_else(|| invalid("set name is missing"))?;
+        match kind.as_str() {
+            "addstickers" => Ok(StickerLink::StickerSet(validate_set_name(&name)?)),
+            "addemoji" => Ok(StickerLink::EmojiSet(validate_set_name(&name)?)),
+            _ => Err(invalid("expect an addstickers or addemoji link")),
+        }
+    }
+
+    /// Name of the set to request with `getStickerSet`.
+    pub fn set_name(&self) -> Option<&str> {
+        match self {
+            StickerLink::StickerSet(name) | StickerLink::EmojiSet(name) => Some(name),
+            StickerLink::CustomEmoji(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for StickerLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StickerLink::StickerSet(name) => write!(f, "https://t.me/addstickers/{}", name),
+            StickerLink::EmojiSet(name) => write!(f, "https://t.me/addemoji/{}", name),
+            StickerLink::CustomEmoji(id) => f.write_str(id),
+        }
+    }
+}
+
+/// Set names have 1 to 64 letters, digits and single underscores, starting with a letter.
+pub fn validate_set_name(name: &str) -> Result<String> {
+    let invalid = |reason: &str| Error::InvalidLink {
+        link: name.to_owned(),
+        reason: reason.to_owned(),
+    };
+    if name.is_empty() || name.len() > 64 {
+        return Err(invalid("set name must have 1 to 64 characters"));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(invalid("set name must start with a letter"));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(invalid(
+            "set name can only contain letters, digits and underscores",
+        ));
+    }
+    if name.contains("__") {
+        return Err(invalid("set name can't contain consecutive underscores"));
+    }
+    Ok(name.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let sticker = StickerLink::StickerSet("in_EDIHDC_by_NaiDrawBot".to_owned());
+        let emoji = StickerLink::EmojiSet("PixelHearts".to_owned());
+        let cases = [
+            ("in_EDIHDC_by_NaiDrawBot", &sticker),
+            ("https://t.me/addstickers/in_EDIHDC_by_NaiDrawBot", &sticker),
+            ("http://t.me/addstickers/in_EDIHDC_by_NaiDrawBot/", &sticker),
+            ("t.me/addstickers/in_EDIHDC_by_NaiDrawBot?ref=1#x", &sticker),
+            (
+                "https://telegram.me/addstickers/in_EDIHDC_by_NaiDrawBot",
+                &sticker,
+            ),
+            (
+                "www.telegram.dog/addstickers/in_EDIHDC_by_NaiDrawBot",
+                &sticker,
+            ),
+            ("tg://addstickers?set=in_EDIHDC_by_NaiDrawBot", &sticker),
+            ("  https://t.me/addemoji/PixelHearts\n", &emoji),
+            ("tg://addemoji?set=PixelHearts", &emoji),
+        ];
+        for (link, expected) in cases {
+            assert_eq!(&StickerLink::parse(link).unwrap(), expected, "{}", link);
+        }
+        assert_eq!(
+            StickerLink::parse("5368324170671202286").unwrap(),
+            StickerLink::CustomEmoji("5368324170671202286".to_owned())
+        );
+        assert_eq!(emoji.to_string(), "https://t.me/addemoji/PixelHearts");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for link in [
+            "",
+            "99999999999999999999999",
+            "https://example.com/addstickers/Cats",
+            "https://t.me/joinchat/Cats",
+            "https://t.me/addstickers/",
+            "https://t.me/addstickers/Cats/1",
+            "tg://addstickers?name=Cats",
+            "ftp://t.me/addstickers/Cats",
+            "1cats",
+            "cats__dogs",
+            "cats-dogs",
+        ] {
+            let err = StickerLink::parse(link).unwrap_err();
+            assert!(matches!(err, Error::InvalidLink { .. }), "{}", link);
+        }
+        assert!(validate_set_name(&"a".repeat(65)).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! Record of the stickers already downloaded into a directory, so that later runs
+//! only fetch what changed.
+
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bot::{Sticker, StickerSet},
+    error::{Error, Result},
+};
+
+pub const MANIFEST_FILE_NAME: &str = ".tsticker.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub title: String,
+    /// Whether file names were transliterated to ASCII, so that sync names new
+    /// stickers alike.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ascii: bool,
+    /// Source of the [`crate::template::NameTemplate`] the stickers were named with,
+    /// missing when they were named otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_template: Option<String>,
+    /// Keyed by `file_unique_id`, which stays the same across bots and time.
+    pub stickers: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Relative to the directory of the manifest.
+    pub file_name: String,
+    pub file_size: u64,
+    /// Kept to upload the sticker again, missing in manifests of older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<String>,
+}
+
+impl Manifest {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST_FILE_NAME)
+    }
+
+    /// Load the manifest in `dir`, or an empty one if there is none yet.
+    ///
+    /// A corrupted manifest is discarded, which only means every sticker is fetched again.
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let content = match tokio::fs::read(Self::path(dir)).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::IoError(e)),
+        };
+        match serde_json::from_slice(&content) {
+            Ok(manifest) => Ok(manifest),
+            Err(e) => {
+                warn!("discard corrupted manifest in {}, {}", dir.display(), e);
+                Ok(Self::default())
+            }
+        }
+    }
+
+    pub async fn save(&self, dir: &Path) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self).map_err(Error::ResponseJsonError)?;
+        let tmp = dir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+        tokio::fs::write(&tmp, content).await?;
+        tokio::fs::rename(&tmp, Self::path(dir)).await?;
+        Ok(())
+    }
+
+    /// Whether `sticker` was downloaded before and its file is still intact.
+    pub async fn is_synced(&self, dir: &Path, sticker: &Sticker) -> bool {
+        let Some(entry) = self.stickers.get(&sticker.file_unique_id) else {
+            return false;
+        };
+        match tokio::fs::metadata(dir.join(&entry.file_name)).await {
+            Ok(meta) => meta.is_file() && meta.len() == entry.file_size,
+            Err(_) => false,
+        }
+    }
+
+    pub fn record(&mut self, sticker: &Sticker, file_name: String, file_size: u64) {
+        self.stickers.insert(
+            sticker.file_unique_id.clone(),
+            ManifestEntry {
+                file_name,
+                file_size,
+                emoji: sticker.emoji.clone(),
+            },
+        );
+    }
+
+    /// Move the files of the stickers keyed by `file_unique_id` in `renames` to their
+    /// new name, e.g. after the name template changed. Files go through a temporary
+    /// name first, so that stickers may swap names.
+    pub async fn rename(&mut self, dir: &Path, renames: &[(String, String)]) -> Result<()> {
+        let mut moved = Vec::with_capacity(renames.len());
+        for (idx, (id, file_name)) in renames.iter().enumerate() {
+            let Some(entry) = self.stickers.get(id) else {
+                continue;
+            };
+            let tmp = dir.join(format!("{}.{}.tmp", MANIFEST_FILE_NAME, idx));
+            tokio::fs::rename(dir.join(&entry.file_name), &tmp).await?;
+            moved.push((id, file_name, tmp));
+        }
+        for (id, file_name, tmp) in moved {
+            let path = dir.join(file_name);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(&tmp, &path).await?;
+            self.stickers.get_mut(id).unwrap().file_name = file_name.clone();
+        }
+        Ok(())
+    }
+
+    pub fn update_set(&mut self, set: &StickerSet) {
+        self.name = set.name.clone();
+        self.title = set.title.clone();
+    }
+
+    /// Entries of stickers that are no longer part of `set`.
+    pub fn stale(&self, set: &StickerSet) -> Vec<(&str, &ManifestEntry)> {
+        self.stickers
+            .iter()
+            .filter(|(id, _)| !set.stickers.iter().any(|s| &s.file_unique_id == *id))
+            .map(|(id, entry)| (id.as_str(), entry))
+            .collect()
+    }
+
+    /// Delete files of stickers removed from `set` and forget them.
+    pub async fn prune(&mut self, dir: &Path, set: &StickerSet) -> Result<Vec<ManifestEntry>> {
+        let stale: Vec<String> = self
+            .stale(set)
+            .into_iter()
+            .map(|(id, _)| id.to_owned())
+            .collect();
+        let mut removed = Vec::with_capacity(stale.len());
+        for id in stale {
+            let entry = self.stickers.remove(&id).unwrap();
+            match tokio::fs::remove_file(dir.join(&entry.file_name)).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::IoError(e)),
+            }
+            removed.push(entry);
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sticker_set() -> StickerSet {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/static_set.json"
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut set = sticker_set();
+        let mut manifest = Manifest::load(dir.path()).await.unwrap();
+        assert_eq!(manifest, Manifest::default());
+
+        manifest.update_set(&set);
+        for (idx, sticker) in set.stickers.iter().enumerate() {
+            let file_name = format!("{}.webp", idx);
+            std::fs::write(dir.path().join(&file_name), b"webp").unwrap();
+            manifest.record(sticker, file_name, 4);
+        }
+        manifest.save(dir.path()).await.unwrap();
+
+        let mut manifest = Manifest::load(dir.path()).await.unwrap();
+        assert_eq!(manifest.name, set.name);
+        assert!(manifest.is_synced(dir.path(), &set.stickers[0]).await);
+        std::fs::write(dir.path().join("1.webp"), b"trunc").unwrap();
+        assert!(!manifest.is_synced(dir.path(), &set.stickers[1]).await);
+
+        set.stickers.remove(0);
+        assert_eq!(manifest.stale(&set).len(), 1);
+        let removed = manifest.prune(dir.path(), &set).await.unwrap();
+        assert_eq!(removed[0].file_name, "0.webp");
+        assert!(!dir.path().join("0.webp").exists());
+        assert!(manifest.stale(&set).is_empty());
+
+        std::fs::write(Manifest::path(dir.path()), b"{").unwrap();
+        assert_eq!(
+            Manifest::load(dir.path()).await.unwrap(),
+            Manifest::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_manifest_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let set = sticker_set();
+        let mut manifest = Manifest::default();
+        let ids: Vec<String> = set.stickers[..2]
+            .iter()
+            .map(|s| s.file_unique_id.clone())
+            .collect();
+        for (idx, sticker) in set.stickers[..2].iter().enumerate() {
+            let file_name = format!("{}.webp", idx);
+            std::fs::write(dir.path().join(&file_name), &sticker.file_unique_id).unwrap();
+            manifest.record(sticker, file_name, sticker.file_unique_id.len() as u64);
+        }
+
+        // stickers swap names, and may move into a sub directory
+        let renames = [
+            (ids[0].clone(), "static/1.webp".to_owned()),
+            (ids[1].clone(), "0.webp".to_owned()),
+        ];
+        manifest.rename(dir.path(), &renames).await.unwrap();
+        assert_eq!(manifest.stickers[&ids[0]].file_name, "static/1.webp");
+        assert_eq!(manifest.stickers[&ids[1]].file_name, "0.webp");
+        for sticker in &set.stickers[..2] {
+            assert!(manifest.is_synced(dir.path(), sticker).await);
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("0.webp")).unwrap(),
+            ids[1]
+        );
+        assert!(!dir.path().join("1.webp").exists());
+    }
+}
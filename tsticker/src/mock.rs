@@ -0,0 +1,551 @@
+//! In-process mock of the Telegram Bot API, for tests that must run offline.
+//!
+//! ```no_run
+//! # async fn run() {
+//! use tsticker::{bot::Bot, mock::MockServer};
+//!
+//! let server = MockServer::start().await;
+//! server.add_sticker_set(serde_json::json!({
+//!     "name": "example", "title": "Example", "sticker_type": "regular", "stickers": []
+//! }));
+//! let bot = Bot::builder(server.token().to_owned())
+//!     .api_url(server.url())
+//!     .login()
+//!     .await
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::{Multipart, Query, State},
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use bytes::Bytes;
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::oneshot};
+
+pub const MOCK_TOKEN: &str = "123456:mock-token";
+
+#[derive(Debug, Clone)]
+struct MockFile {
+    file_unique_id: String,
+    file_path: String,
+    content: Bytes,
+}
+
+#[derive(Debug, Clone)]
+struct MockFailure {
+    status: StatusCode,
+    description: String,
+    retry_after: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    token: String,
+    sticker_sets: HashMap<String, Value>,
+    files: HashMap<String, MockFile>,
+    failures: HashMap<String, VecDeque<MockFailure>>,
+    hits: HashMap<String, usize>,
+    latency: Duration,
+    in_flight: usize,
+    max_in_flight: usize,
+    next_file: usize,
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            token: MOCK_TOKEN.to_owned(),
+            ..Default::default()
+        }));
+        let app = Router::new().fallback(dispatch).with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("fail to bind mock server");
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, signal) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async {
+                    signal.await.ok();
+                })
+                .await
+                .ok();
+        });
+
+        Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}", self.addr)).unwrap()
+    }
+
+    pub fn token(&self) -> &str {
+        MOCK_TOKEN
+    }
+
+    /// Register the `result` object of a `getStickerSet` response.
+    ///
+    /// Every sticker and thumbnail in the set becomes downloadable through `getFile`,
+    /// with its `file_unique_id` as content unless overridden by [`MockServer::add_file`].
+    pub fn add_sticker_set(&self, set: Value) {
+        let name = set["name"]
+            .as_str()
+            .expect("sticker set must have a name")
+            .to_owned();
+        let stickers = set["stickers"].as_array().cloned().unwrap_or_default();
+        for sticker in stickers.iter() {
+            for file in [
+                &sticker["thumbnail"],
+                &sticker["thumb"],
+                &sticker["premium_animation"],
+                sticker,
+            ] {
+                let (Some(file_id), Some(unique_id)) =
+                    (file["file_id"].as_str(), file["file_unique_id"].as_str())
+                else {
+                    continue;
+                };
+                let mut state = self.state.lock().unwrap();
+                if !state.files.contains_key(file_id) {
+                    let content = Bytes::from(unique_id.to_owned());
+                    state.insert_file(file_id, unique_id, content);
+                }
+            }
+        }
+        self.state.lock().unwrap().sticker_sets.insert(name, set);
+    }
+
+    /// Register a file for `getFile`, replacing any existing content.
+    pub fn add_file(&self, file_id: &str, file_unique_id: &str, content: impl Into<Bytes>) {
+        self.state
+            .lock()
+            .unwrap()
+            .insert_file(file_id, file_unique_id, content.into());
+    }
+
+    /// Register a file whose `getFile` path is an absolute path on this machine,
+    /// as returned by a Bot API server running in local mode.
+    pub fn add_local_file(&self, file_id: &str, file_unique_id: &str, path: &Path) {
+        let content = std::fs::read(path).expect("fail to read local file");
+        self.state.lock().unwrap().files.insert(
+            file_id.to_owned(),
+            MockFile {
+                file_unique_id: file_unique_id.to_owned(),
+                file_path: path.to_string_lossy().into_owned(),
+                content: content.into(),
+            },
+        );
+    }
+
+    /// Make the next call of `method` fail with the given status and description.
+    ///
+    /// `method` is a Bot API method name, or `"file"` for file downloads.
+    pub fn fail(&self, method: &str, status: u16, description: &str, retry_after: Option<u64>) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .entry(method.to_owned())
+            .or_default()
+            .push_back(MockFailure {
+                status: StatusCode::from_u16(status).expect("invalid status code"),
+                description: description.to_owned(),
+                retry_after,
+            });
+    }
+
+    /// Delay every response, to observe how many requests are served at the same time.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Highest number of requests that were in flight at the same time.
+    pub fn max_in_flight(&self) -> usize {
+        self.state.lock().unwrap().max_in_flight
+    }
+
+    /// Number of requests received for `method` (`"file"` for file downloads).
+    pub fn hits(&self, method: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .hits
+            .get(method)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+impl MockState {
+    fn insert_file(&mut self, file_id: &str, file_unique_id: &str, content: Bytes) {
+        self.files.insert(
+            file_id.to_owned(),
+            MockFile {
+                file_unique_id: file_unique_id.to_owned(),
+                file_path: format!("stickers/{}", file_unique_id),
+                content,
+            },
+        );
+    }
+
+    /// Report the size of the content served for `sticker`, as Telegram does.
+    fn fill_file_size(&self, sticker: &mut Value) {
+        let file = sticker["file_id"]
+            .as_str()
+            .and_then(|file_id| self.files.get(file_id));
+        if let Some(file) = file {
+            sticker["file_size"] = json!(file.content.len());
+        }
+    }
+
+    /// Store an uploaded file, returning its `(file_id, file_unique_id)`.
+    fn new_file(&mut self, content: Bytes) -> (String, String) {
+        self.next_file += 1;
+        let file_id = format!("uploaded{}", self.next_file);
+        let unique_id = format!("AgADUploaded{}", self.next_file);
+        self.insert_file(&file_id, &unique_id, content);
+        (file_id, unique_id)
+    }
+
+    /// Build the sticker object of an `InputSticker` added to the set `set_name`.
+    fn new_sticker(
+        &mut self,
+        input: &Value,
+        set_name: &str,
+        sticker_type: &str,
+        uploads: &Uploads,
+    ) -> Result<Value, &'static str> {
+        let source = input["sticker"].as_str().unwrap_or_default();
+        let content = match source.strip_prefix("attach://") {
+            Some(name) => uploads.get(name).cloned(),
+            None => self.files.get(source).map(|file| file.content.clone()),
+        }
+        .ok_or("wrong file identifier/HTTP URL specified")?;
+        let emoji = input["emoji_list"]
+            .as_array()
+            .and_then(|list| list.first())
+            .ok_or("emoji list is empty")?
+            .clone();
+        let format = input["format"].as_str().unwrap_or_default();
+        let (file_id, unique_id) = self.new_file(content.clone());
+        let mut sticker = json!({
+            "file_id": file_id,
+            "file_unique_id": unique_id,
+            "type": sticker_type,
+            "width": 512,
+            "height": 512,
+            "is_animated": format == "animated",
+            "is_video": format == "video",
+            "emoji": emoji,
+            "set_name": set_name,
+            "file_size": content.len(),
+        });
+        if !input["mask_position"].is_null() {
+            sticker["mask_position"] = input["mask_position"].clone();
+        }
+        Ok(sticker)
+    }
+
+    fn take_failure(&mut self, method: &str) -> Option<MockFailure> {
+        self.failures.get_mut(method)?.pop_front()
+    }
+}
+
+fn api_error(status: StatusCode, description: &str, retry_after: Option<u64>) -> Response {
+    let mut body = json!({
+        "ok": false,
+        "error_code": status.as_u16(),
+        "description": description,
+    });
+    if let Some(retry_after) = retry_after {
+        body["parameters"] = json!({ "retry_after": retry_after });
+    }
+    (status, Json(body)).into_response()
+}
+
+fn api_ok(result: Value) -> Response {
+    Json(json!({ "ok": true, "result": result })).into_response()
+}
+
+/// Files uploaded with a multipart request, by field name.
+type Uploads = HashMap<String, Bytes>;
+
+async fn dispatch(
+    State(state): State<Arc<Mutex<MockState>>>,
+    Query(mut params): Query<HashMap<String, String>>,
+    uri: Uri,
+    multipart: Option<Multipart>,
+) -> Response {
+    let mut uploads = Uploads::new();
+    if let Some(mut multipart) = multipart {
+        while let Ok(Some(field)) = multipart.next_field().await {
+            let name = field.name().unwrap_or_default().to_owned();
+            let is_file = field.file_name().is_some();
+            let Ok(data) = field.bytes().await else {
+                return api_error(StatusCode::BAD_REQUEST, "Bad Request: broken body", None);
+            };
+            if is_file {
+                uploads.insert(name, data);
+            } else {
+                params.insert(name, String::from_utf8_lossy(&data).into_owned());
+            }
+        }
+    }
+    let latency = {
+        let mut state = state.lock().unwrap();
+        state.in_flight += 1;
+        state.max_in_flight = state.max_in_flight.max(state.in_flight);
+        state.latency
+    };
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+    let resp = respond(&mut state.lock().unwrap(), &params, &uploads, &uri);
+    state.lock().unwrap().in_flight -= 1;
+    resp
+}
+
+fn respond(
+    state: &mut MockState,
+    params: &HashMap<String, String>,
+    uploads: &Uploads,
+    uri: &Uri,
+) -> Response {
+    let segments: Vec<&str> = uri.path().trim_start_matches('/').splitn(3, '/').collect();
+    match segments.as_slice() {
+        ["file", bot, path] => {
+            *state.hits.entry("file".to_owned()).or_default() += 1;
+            if bot.strip_prefix("bot") != Some(state.token.as_str()) {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            if let Some(failure) = state.take_failure("file") {
+                return failure.status.into_response();
+            }
+            match state.files.values().find(|f| f.file_path == *path) {
+                Some(file) => Body::from(file.content.clone()).into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+        [bot, method] => {
+            *state.hits.entry(method.to_string()).or_default() += 1;
+            if bot.strip_prefix("bot") != Some(state.token.as_str()) {
+                return api_error(StatusCode::UNAUTHORIZED, "Unauthorized", None);
+            }
+            if let Some(failure) = state.take_failure(method) {
+                return api_error(failure.status, &failure.description, failure.retry_after);
+            }
+            call_method(state, method, params, uploads)
+        }
+        _ => api_error(StatusCode::NOT_FOUND, "Not Found", None),
+    }
+}
+
+fn call_method(
+    state: &mut MockState,
+    method: &str,
+    params: &HashMap<String, String>,
+    uploads: &Uploads,
+) -> Response {
+    let param = |key: &str| params.get(key).map(String::as_str).unwrap_or_default();
+    let bad_request = |description: &str| {
+        api_error(
+            StatusCode::BAD_REQUEST,
+            &format!("Bad Request: {}", description),
+            None,
+        )
+    };
+    match method {
+        "getMe" => api_ok(json!({
+            "id": 123456,
+            "is_bot": true,
+            "first_name": "Mock",
+            "username": "mock_bot",
+        })),
+        "getStickerSet" => match state.sticker_sets.get(param("name")) {
+            Some(set) => {
+                let mut set = set.clone();
+                if let Some(stickers) = set["stickers"].as_array_mut() {
+                    stickers.iter_mut().for_each(|s| state.fill_file_size(s));
+                }
+                api_ok(set)
+            }
+            None => api_error(
+                StatusCode::BAD_REQUEST,
+                "Bad Request: STICKERSET_INVALID",
+                None,
+            ),
+        },
+        "getCustomEmojiStickers" => {
+            let Ok(ids) = serde_json::from_str::<Vec<String>>(param("custom_emoji_ids")) else {
+                return api_error(
+                    StatusCode::BAD_REQUEST,
+                    "Bad Request: can't parse custom emoji identifiers JSON object",
+                    None,
+                );
+            };
+            if ids.len() > 200 {
+                return api_error(
+                    StatusCode::BAD_REQUEST,
+                    "Bad Request: too many custom emoji identifiers specified",
+                    None,
+                );
+            }
+            let mut stickers: Vec<Value> = ids
+                .iter()
+                .filter_map(|id| {
+                    state
+                        .sticker_sets
+                        .values()
+                        .filter_map(|set| set["stickers"].as_array())
+                        .flatten()
+                        .find(|sticker| sticker["custom_emoji_id"].as_str() == Some(id))
+                        .cloned()
+                })
+                .collect();
+            stickers.iter_mut().for_each(|s| state.fill_file_size(s));
+            api_ok(json!(stickers))
+        }
+        "getFile" => match state.files.get(param("file_id")) {
+            Some(file) => api_ok(json!({
+                "file_id": param("file_id"),
+                "file_unique_id": file.file_unique_id,
+                "file_size": file.content.len(),
+                "file_path": file.file_path,
+            })),
+            None => api_error(
+                StatusCode::BAD_REQUEST,
+                "Bad Request: invalid file_id",
+                None,
+            ),
+        },
+        "uploadStickerFile" => {
+            let Some(content) = uploads.get("sticker") else {
+                return bad_request("there is no sticker file in the request");
+            };
+            let (file_id, unique_id) = state.new_file(content.clone());
+            api_ok(json!({
+                "file_id": file_id,
+                "file_unique_id": unique_id,
+                "file_size": content.len(),
+            }))
+        }
+        "createNewStickerSet" => {
+            let name = param("name");
+            if !name.ends_with("_by_mock_bot") {
+                return bad_request("invalid sticker set name is specified");
+            }
+            if state.sticker_sets.contains_key(name) {
+                return bad_request("sticker set name is already occupied");
+            }
+            let Ok(inputs) = serde_json::from_str::<Vec<Value>>(param("stickers")) else {
+                return bad_request("can't parse stickers JSON object");
+            };
+            if inputs.is_empty() || inputs.len() > 50 {
+                return bad_request("STICKERS_TOO_MUCH");
+            }
+            let sticker_type = match param("sticker_type") {
+                "" => "regular",
+                ty => ty,
+            };
+            let mut stickers = Vec::new();
+            for input in inputs.iter() {
+                match state.new_sticker(input, name, sticker_type, uploads) {
+                    Ok(sticker) => stickers.push(sticker),
+                    Err(description) => return bad_request(description),
+                }
+            }
+            let set = json!({
+                "name": name,
+                "title": param("title"),
+                "sticker_type": sticker_type,
+                "stickers": stickers,
+            });
+            state.sticker_sets.insert(name.to_owned(), set);
+            api_ok(json!(true))
+        }
+        "addStickerToSet" => {
+            let Ok(input) = serde_json::from_str::<Value>(param("sticker")) else {
+                return bad_request("can't parse sticker JSON object");
+            };
+            let Some(set) = state.sticker_sets.get(param("name")) else {
+                return bad_request("STICKERSET_INVALID");
+            };
+            if set["stickers"].as_array().map_or(0, Vec::len) >= 120 {
+                return bad_request("STICKERS_TOO_MUCH");
+            }
+            let sticker_type = set["sticker_type"].as_str().unwrap_or("regular").to_owned();
+            match state.new_sticker(&input, param("name"), &sticker_type, uploads) {
+                Ok(sticker) => {
+                    let set = state.sticker_sets.get_mut(param("name")).unwrap();
+                    set["stickers"].as_array_mut().unwrap().push(sticker);
+                    api_ok(json!(true))
+                }
+                Err(description) => bad_request(description),
+            }
+        }
+        "setStickerEmojiList" => {
+            let Ok(emoji_list) = serde_json::from_str::<Vec<String>>(param("emoji_list")) else {
+                return bad_request("can't parse emoji list");
+            };
+            let sticker = state
+                .sticker_sets
+                .values_mut()
+                .filter_map(|set| set["stickers"].as_array_mut())
+                .flatten()
+                .find(|sticker| sticker["file_id"] == param("sticker"));
+            match (sticker, emoji_list.first()) {
+                (Some(sticker), Some(emoji)) => {
+                    sticker["emoji"] = json!(emoji);
+                    api_ok(json!(true))
+                }
+                (None, _) => bad_request("STICKER_ID_INVALID"),
+                (_, None) => bad_request("emoji list is empty"),
+            }
+        }
+        "setStickerSetTitle" => match state.sticker_sets.get_mut(param("name")) {
+            Some(set) => {
+                set["title"] = json!(param("title"));
+                api_ok(json!(true))
+            }
+            None => bad_request("STICKERSET_INVALID"),
+        },
+        "deleteStickerSet" => match state.sticker_sets.remove(param("name")) {
+            Some(_) => api_ok(json!(true)),
+            None => bad_request("STICKERSET_INVALID"),
+        },
+        _ => api_error(StatusCode::NOT_FOUND, "Not Found", None),
+    }
+}
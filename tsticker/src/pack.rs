@@ -0,0 +1,160 @@
+//! Metadata of a downloaded sticker set, written next to its files so the set can be
+//! rebuilt or imported elsewhere later.
+//!
+//! Unlike [`crate::manifest`], which only tracks what is on disk, `pack.json` keeps the
+//! order of the set and everything the Bot API returned for each sticker.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bot::{PhotoSize, Sticker, StickerSet, StickerSetType},
+    error::{Error, Result},
+    manifest::Manifest,
+    upload::StickerFormat,
+};
+
+pub const PACK_FILE_NAME: &str = "pack.json";
+pub const PACK_CSV_FILE_NAME: &str = "pack.csv";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pack {
+    pub name: String,
+    pub title: String,
+    pub sticker_type: StickerSetType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<PhotoSize>,
+    /// In the order of the set.
+    pub stickers: Vec<PackSticker>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackSticker {
+    pub index: usize,
+    /// Relative to the directory of the pack, missing if the sticker is not downloaded.
+    pub file_name: Option<String>,
+    #[serde(flatten)]
+    pub sticker: Sticker,
+}
+
+impl Pack {
+    /// Metadata of `set`, with the file names recorded in `manifest`.
+    pub fn new(set: &StickerSet, manifest: &Manifest) -> Self {
+        let stickers = set
+            .stickers
+            .iter()
+            .enumerate()
+            .map(|(index, sticker)| PackSticker {
+                index,
+                file_name: manifest
+                    .stickers
+                    .get(&sticker.file_unique_id)
+                    .map(|entry| entry.file_name.clone()),
+                sticker: sticker.clone(),
+            })
+            .collect();
+        Self {
+            name: set.name.clone(),
+            title: set.title.clone(),
+            sticker_type: set.sticker_type.clone(),
+            thumbnail: set.thumbnail.clone(),
+            stickers,
+        }
+    }
+
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(PACK_FILE_NAME)
+    }
+
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let content = tokio::fs::read(Self::path(dir)).await?;
+        serde_json::from_slice(&content).map_err(Error::ResponseJsonError)
+    }
+
+    pub async fn save(&self, dir: &Path) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self).map_err(Error::ResponseJsonError)?;
+        write_file(dir, PACK_FILE_NAME, content).await
+    }
+
+    /// Write one row per sticker to `pack.csv`, for spreadsheets.
+    pub async fn save_csv(&self, dir: &Path) -> Result<()> {
+        write_file(dir, PACK_CSV_FILE_NAME, self.to_csv().into_bytes()).await
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "index,file_name,file_unique_id,file_id,emoji,type,format,width,height,custom_emoji_id\n",
+        );
+        for entry in self.stickers.iter() {
+            let sticker = &entry.sticker;
+            let row = [
+                entry.index.to_string(),
+                entry.file_name.clone().unwrap_or_default(),
+                sticker.file_unique_id.clone(),
+                sticker.file_id.clone(),
+                sticker.emoji.clone().unwrap_or_default(),
+                sticker.ty.to_string(),
+                StickerFormat::from(sticker.file_ext()).as_str().to_owned(),
+                sticker.width.to_string(),
+                sticker.height.to_string(),
+                sticker.custom_emoji_id.clone().unwrap_or_default(),
+            ];
+            let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+async fn write_file(dir: &Path, file_name: &str, content: Vec<u8>) -> Result<()> {
+    let tmp = dir.join(format!("{}.tmp", file_name));
+    tokio::fs::write(&tmp, content).await?;
+    tokio::fs::rename(&tmp, dir.join(file_name)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pack() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mask_set.json");
+        let mut set: StickerSet =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        set.stickers[0].emoji = Some("a,\"b\"".to_owned());
+        let mut manifest = Manifest::default();
+        manifest.record(&set.stickers[0], "0.webp".to_owned(), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let pack = Pack::new(&set, &manifest);
+        pack.save(dir.path()).await.unwrap();
+        pack.save_csv(dir.path()).await.unwrap();
+
+        let loaded = Pack::load(dir.path()).await.unwrap();
+        assert_eq!(loaded.title, set.title);
+        assert_eq!(loaded.stickers.len(), set.stickers.len());
+        assert_eq!(loaded.stickers[0].file_name.as_deref(), Some("0.webp"));
+        assert_eq!(loaded.stickers[1].file_name, None);
+        assert_eq!(
+            loaded.stickers[0].sticker.mask_position,
+            set.stickers[0].mask_position
+        );
+
+        let csv = std::fs::read_to_string(dir.path().join(PACK_CSV_FILE_NAME)).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), set.stickers.len() + 1);
+        assert!(lines[1].starts_with("0,0.webp,"));
+        assert!(lines[1].contains(",\"a,\"\"b\"\"\",mask,static,"));
+    }
+}
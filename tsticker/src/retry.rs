@@ -0,0 +1,192 @@
+use std::{future::Future, time::Duration};
+
+use log::warn;
+use rand::Rng;
+
+use crate::error::{Error, Result};
+
+/// How [`crate::bot::Bot`] retries failed requests.
+///
+/// Rate limited requests wait for the `retry_after` Telegram asked for, while network
+/// failures and 5xx responses back off exponentially from `base_delay` with full jitter.
+/// Requests that change a set, like `addStickerToSet`, are only retried when rate limited
+/// or when they couldn't connect.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+    /// Give up instead of sleeping when Telegram asks to wait longer than this.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before the `attempt`-th retry (starting from 0), or `None` to give up.
+    pub fn delay(&self, attempt: u32, err: &Error) -> Option<Duration> {
+        self.delay_if(attempt, err, is_transient(err))
+    }
+
+    /// Like [`RetryPolicy::delay`], for requests that change state. Those are only sent
+    /// again when Telegram can't have applied them, since a request that timed out may
+    /// still have been.
+    pub fn delay_unapplied(&self, attempt: u32, err: &Error) -> Option<Duration> {
+        self.delay_if(attempt, err, is_unapplied(err))
+    }
+
+    fn delay_if(&self, attempt: u32, err: &Error, retry: bool) -> Option<Duration> {
+        if attempt >= self.max_retries || !retry {
+            return None;
+        }
+        if let Some(retry_after) = err.retry_after() {
+            return (retry_after <= self.max_retry_after).then_some(retry_after);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        if self.jitter {
+            Some(backoff.mul_f64(rand::thread_rng().gen_range(0.0..=1.0)))
+        } else {
+            Some(backoff)
+        }
+    }
+
+    pub(crate) async fn run<T, F, Fut>(&self, target: &str, f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.run_with(target, Self::delay, f).await
+    }
+
+    /// Run a request that changes state, see [`RetryPolicy::delay_unapplied`].
+    pub(crate) async fn run_unapplied<T, F, Fut>(&self, target: &str, f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.run_with(target, Self::delay_unapplied, f).await
+    }
+
+    async fn run_with<T, F, Fut>(
+        &self,
+        target: &str,
+        delay: fn(&Self, u32, &Error) -> Option<Duration>,
+        mut f: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Ok(v) => return Ok(v),
+                Err(err) => {
+                    let Some(delay) = delay(self, attempt, &err) else {
+                        return Err(err);
+                    };
+                    warn!("request {} failed ({}), retry in {:?}", target, err, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::Api { code, .. } => *code == 429 || *code >= 500,
+        Error::RequestError(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.is_request()
+                || e.status()
+                    .is_some_and(|s| s.is_server_error() || s.as_u16() == 429)
+        }
+        _ => false,
+    }
+}
+
+/// Rate limited, or never reached Telegram.
+fn is_unapplied(err: &Error) -> bool {
+    match err {
+        Error::Api { code, .. } => *code == 429,
+        Error::RequestError(e) => e.is_connect() || e.status().is_some_and(|s| s.as_u16() == 429),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn api_error(code: i32, retry_after: Option<u64>) -> Error {
+        Error::Api {
+            code,
+            description: String::new(),
+            retry_after: retry_after.map(Duration::from_secs),
+            migrate_to_chat_id: None,
+        }
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.delay(0, &api_error(502, None)),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            policy.delay(2, &api_error(502, None)),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(policy.delay(3, &api_error(502, None)), None);
+        assert_eq!(
+            policy.delay(0, &api_error(429, Some(7))),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(policy.delay(0, &api_error(429, Some(600))), None);
+        assert_eq!(policy.delay(0, &api_error(400, None)), None);
+        assert_eq!(RetryPolicy::none().delay(0, &api_error(502, None)), None);
+
+        assert_eq!(policy.delay_unapplied(0, &api_error(502, None)), None);
+        assert_eq!(
+            policy.delay_unapplied(0, &api_error(429, Some(7))),
+            Some(Duration::from_secs(7))
+        );
+    }
+
+    #[test]
+    fn test_delay_jitter() {
+        let policy = RetryPolicy::default();
+        for attempt in 0..3 {
+            let delay = policy.delay(attempt, &api_error(500, None)).unwrap();
+            assert!(delay <= policy.base_delay * 2u32.pow(attempt));
+        }
+    }
+}
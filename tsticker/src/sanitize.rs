@@ -0,0 +1,177 @@
+//! Turn set titles, emoji names and file name templates into names that are safe on
+//! Linux, macOS and Windows.
+
+use std::{fmt, str::FromStr};
+
+use deunicode::deunicode_with_tofu;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::bot::StickerSet;
+
+/// In bytes, leaving room for suffixes like `.tmp` within the 255 bytes most
+/// filesystems allow.
+pub const MAX_FILE_NAME_LEN: usize = 240;
+
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+    "COM8", "COM9", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// What the directory of a sticker set is named after.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SetFolder {
+    #[default]
+    Title,
+    Name,
+}
+
+impl SetFolder {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SetFolder::Title => "title",
+            SetFolder::Name => "name",
+        }
+    }
+}
+
+impl fmt::Display for SetFolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SetFolder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "title" => Ok(SetFolder::Title),
+            "name" => Ok(SetFolder::Name),
+            _ => Err(format!("unknown set folder {}, expect title or name", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sanitizer {
+    /// Transliterate names to ASCII, e.g. `Привет` to `Privet`.
+    pub ascii: bool,
+    /// Maximum length of each path component in bytes.
+    pub max_len: usize,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self {
+            ascii: false,
+            max_len: MAX_FILE_NAME_LEN,
+        }
+    }
+}
+
+impl Sanitizer {
+    /// A single path component, never empty, `.` or `..`.
+    pub fn file_name(&self, name: &str) -> String {
+        let name: String = name.nfc().collect();
+        let name = if self.ascii {
+            deunicode_with_tofu(&name, "_")
+        } else {
+            name
+        };
+        let name: String = name
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+        // Windows drops trailing dots and spaces.
+        let mut name = name.trim().trim_end_matches(['.', ' ']).to_owned();
+
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        if RESERVED_NAMES
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        {
+            name.insert(0, '_');
+        }
+        if name.len() > self.max_len {
+            name = truncate(&name, self.max_len);
+        }
+        if name.is_empty() || name.chars().all(|c| c == '.') {
+            name = "_".to_owned();
+        }
+        name
+    }
+
+    /// A relative path separated by `/`, sanitizing every component, so that it always
+    /// stays inside the directory it is joined to.
+    pub fn relative_path(&self, path: &str) -> String {
+        path.split(['/', '\\'])
+            .filter(|component| !component.is_empty())
+            .map(|component| self.file_name(component))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Name of the directory of `set`.
+    pub fn set_dir_name(&self, set: &StickerSet, folder: SetFolder) -> String {
+        match folder {
+            SetFolder::Title => self.file_name(&set.title),
+            SetFolder::Name => self.file_name(&set.name),
+        }
+    }
+}
+
+/// Cut `name` to `max_len` bytes, keeping a short extension.
+fn truncate(name: &str, max_len: usize) -> String {
+    let (stem, ext) = match name.rfind('.') {
+        Some(pos) if pos > 0 && name.len() - pos <= 16 => name.split_at(pos),
+        _ => (name, ""),
+    };
+    let mut end = max_len.saturating_sub(ext.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", stem[..end].trim_end_matches(['.', ' ']), ext)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        let sanitizer = Sanitizer::default();
+        assert_eq!(sanitizer.file_name("a/b\\c:d*e?"), "a_b_c_d_e_");
+        assert_eq!(sanitizer.file_name("tab\there\n"), "tab_here_");
+        assert_eq!(sanitizer.file_name(".."), "_");
+        assert_eq!(sanitizer.file_name("  "), "_");
+        assert_eq!(sanitizer.file_name("title. "), "title");
+        assert_eq!(sanitizer.file_name("con"), "_con");
+        assert_eq!(sanitizer.file_name("Com1.webp"), "_Com1.webp");
+        assert_eq!(sanitizer.file_name("console.webp"), "console.webp");
+        assert_eq!(sanitizer.file_name("Cafe\u{301}"), "Caf\u{e9}");
+        assert_eq!(sanitizer.file_name("\u{1f600} Привет"), "\u{1f600} Привет");
+
+        let ascii = Sanitizer {
+            ascii: true,
+            ..Sanitizer::default()
+        };
+        assert_eq!(ascii.file_name("Привет Café"), "Privet Cafe");
+
+        let long = format!("{}.webp", "\u{1f600}".repeat(100));
+        let name = sanitizer.file_name(&long);
+        assert!(name.len() <= MAX_FILE_NAME_LEN);
+        assert!(name.ends_with("\u{1f600}.webp"));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let sanitizer = Sanitizer::default();
+        assert_eq!(sanitizer.relative_path("video/0.webm"), "video/0.webm");
+        assert_eq!(sanitizer.relative_path("/../a//b:c"), "_/a/b_c");
+    }
+}
@@ -0,0 +1,295 @@
+//! File name templates such as `{index:03}_{emoji_name}_{unique_id}.{ext}`.
+//!
+//! | Placeholder    | Value                                              |
+//! |----------------|----------------------------------------------------|
+//! | `{set}`        | name of the set                                    |
+//! | `{title}`      | title of the set                                   |
+//! | `{index}`      | position in the set, starting from 0               |
+//! | `{emoji}`      | the emoji itself                                   |
+//! | `{emoji_name}` | name of the emoji, e.g. `grinning face`            |
+//! | `{codepoints}` | code points of the emoji, e.g. `1f600`             |
+//! | `{unique_id}`  | `file_unique_id`, stable across bots and time      |
+//! | `{file_id}`    | `file_id`, only valid for the bot                  |
+//! | `{type}`       | `regular`, `mask` or `custom_emoji`                |
+//! | `{format}`     | `static`, `animated` or `video`                    |
+//! | `{width}`      | width in pixels                                    |
+//! | `{height}`     | height in pixels                                   |
+//! | `{ext}`        | `webp`, `tgs` or `webm`                            |
+//!
+//! Numbers can be padded with zeros, e.g. `{index:03}`, and `{{` or `}}` produce a
+//! literal brace. `/` in the template creates sub directories in the set directory.
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use crate::{
+    bot::{Sticker, StickerSet},
+    download::NamingFn,
+    error::{Error, Result},
+    upload::StickerFormat,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Set,
+    Title,
+    Index,
+    Emoji,
+    EmojiName,
+    Codepoints,
+    UniqueId,
+    FileId,
+    Type,
+    Format,
+    Width,
+    Height,
+    Ext,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "set" => Field::Set,
+            "title" => Field::Title,
+            "index" => Field::Index,
+            "emoji" => Field::Emoji,
+            "emoji_name" => Field::EmojiName,
+            "codepoints" => Field::Codepoints,
+            "unique_id" => Field::UniqueId,
+            "file_id" => Field::FileId,
+            "type" => Field::Type,
+            "format" => Field::Format,
+            "width" => Field::Width,
+            "height" => Field::Height,
+            "ext" => Field::Ext,
+            _ => return None,
+        })
+    }
+
+    fn is_number(&self) -> bool {
+        matches!(self, Field::Index | Field::Width | Field::Height)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field { field: Field, width: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let invalid = |reason: String| Error::InvalidTemplate {
+            template: template.to_owned(),
+            reason,
+        };
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(invalid("unmatched }".to_owned())),
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => return Err(invalid("unclosed {".to_owned())),
+                            Some(c) => placeholder.push(c),
+                        }
+                    }
+                    let (name, spec) = match placeholder.split_once(':') {
+                        Some((name, spec)) => (name, Some(spec)),
+                        None => (placeholder.as_str(), None),
+                    };
+                    let field = Field::parse(name)
+                        .ok_or_else(|| invalid(format!("unknown placeholder {{{}}}", name)))?;
+                    let width = match spec {
+                        None => 0,
+                        Some(spec) if field.is_number() && spec.starts_with('0') => spec
+                            .parse()
+                            .map_err(|_| invalid(format!("invalid padding {{{}}}", placeholder)))?,
+                        Some(_) => {
+                            return Err(invalid(format!(
+                                "only {{index}}, {{width}} and {{height}} can be padded, e.g. {{index:03}}, got {{{}}}",
+                                placeholder
+                            )))
+                        }
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field { field, width });
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        let has = |wanted: &[Field]| {
+            segments
+                .iter()
+                .any(|s| matches!(s, Segment::Field { field, .. } if wanted.contains(field)))
+        };
+        if !has(&[Field::Ext]) {
+            return Err(invalid("{ext} is required".to_owned()));
+        }
+        if !has(&[Field::Index, Field::UniqueId, Field::FileId]) {
+            return Err(invalid(
+                "one of {index}, {unique_id} or {file_id} is required to tell stickers apart"
+                    .to_owned(),
+            ));
+        }
+        let escapes = segments.iter().any(|s| match s {
+            Segment::Literal(literal) => literal.split('/').any(|part| part == ".."),
+            _ => false,
+        });
+        if escapes || template.starts_with('/') {
+            return Err(invalid("must stay inside the set directory".to_owned()));
+        }
+        Ok(Self {
+            source: template.to_owned(),
+            segments,
+        })
+    }
+
+    /// File name of the `index`-th sticker of `set`.
+    ///
+    /// `/` in values is replaced, so only the template itself creates directories.
+    pub fn render(&self, set: &StickerSet, index: usize, sticker: &Sticker) -> String {
+        let mut name = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(literal) => name.push_str(literal),
+                Segment::Field { field, width } => {
+                    let value = match field {
+                        Field::Set => set.name.clone(),
+                        Field::Title => set.title.clone(),
+                        Field::Index => format!("{:0width$}", index, width = width),
+                        Field::Emoji => sticker.emoji.clone().unwrap_or_default(),
+                        Field::EmojiName => emoji_name(sticker).to_owned(),
+                        Field::Codepoints => codepoints(sticker),
+                        Field::UniqueId => sticker.file_unique_id.clone(),
+                        Field::FileId => sticker.file_id.clone(),
+                        Field::Type => sticker.ty.to_string(),
+                        Field::Format => {
+                            StickerFormat::from(sticker.file_ext()).as_str().to_owned()
+                        }
+                        Field::Width => format!("{:0width$}", sticker.width, width = width),
+                        Field::Height => format!("{:0width$}", sticker.height, width = width),
+                        Field::Ext => sticker.file_ext().to_string(),
+                    };
+                    name.push_str(&value.replace(['/', '\\'], "_"));
+                }
+            }
+        }
+        name
+    }
+
+    pub fn naming(self) -> NamingFn {
+        Arc::new(move |set, index, sticker| self.render(set, index, sticker))
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+pub(crate) fn emoji_name(sticker: &Sticker) -> &'static str {
+    sticker
+        .emoji
+        .as_deref()
+        .and_then(emojis::get)
+        .map(|e| e.name())
+        .unwrap_or("emoji_missing")
+}
+
+fn codepoints(sticker: &Sticker) -> String {
+    sticker
+        .emoji
+        .as_deref()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| format!("{:x}", c as u32))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sticker_set() -> StickerSet {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/static_set.json"
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_render() {
+        let mut set = sticker_set();
+        set.stickers[1].emoji = Some("\u{2764}\u{fe0f}".to_owned());
+        let sticker = &set.stickers[1];
+        let template: NameTemplate =
+            "{{{set}}}/{index:03}_{emoji_name}_{codepoints}_{unique_id}_{width}x{height}.{ext}"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            template.render(&set, 7, sticker),
+            format!(
+                "{{{}}}/007_red heart_2764-fe0f_{}_{}x{}.webp",
+                set.name, sticker.file_unique_id, sticker.width, sticker.height
+            )
+        );
+
+        set.title = "a/b".to_owned();
+        let template = NameTemplate::parse("{title}_{index}.{ext}").unwrap();
+        assert_eq!(template.render(&set, 0, sticker), "a_b_0.webp");
+        assert_eq!(template.to_string(), "{title}_{index}.{ext}");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for template in [
+            "{index}",
+            "{emoji}.{ext}",
+            "{index}_{unknown}.{ext}",
+            "{index.{ext}",
+            "{index}}.{ext}",
+            "{emoji:03}_{index}.{ext}",
+            "{index:3}.{ext}",
+            "../{index}.{ext}",
+            "/tmp/{index}.{ext}",
+        ] {
+            let err = NameTemplate::parse(template).unwrap_err();
+            assert!(matches!(err, Error::InvalidTemplate { .. }), "{}", template);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Animated stickers (`is_animated`) are Lottie animations compressed with gzip,
+//! known as TGS files.
+
+use std::{io::Read, time::Duration};
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// Upper bound of the decompressed size, guarding against gzip bombs.
+const MAX_LOTTIE_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TgsMetadata {
+    #[serde(rename = "v", default)]
+    pub version: String,
+    #[serde(rename = "fr")]
+    pub frame_rate: f64,
+    #[serde(rename = "ip")]
+    pub in_point: f64,
+    #[serde(rename = "op")]
+    pub out_point: f64,
+    #[serde(rename = "w")]
+    pub width: u32,
+    #[serde(rename = "h")]
+    pub height: u32,
+}
+
+impl TgsMetadata {
+    pub fn frame_count(&self) -> u32 {
+        (self.out_point - self.in_point).max(0.0).round() as u32
+    }
+
+    pub fn duration(&self) -> Duration {
+        if self.frame_rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.frame_count() as f64 / self.frame_rate)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tgs {
+    metadata: TgsMetadata,
+    animation: Value,
+}
+
+impl Tgs {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let animation: Value = serde_json::from_slice(&decompress(data)?)
+            .map_err(|e| Error::TgsError(e.to_string()))?;
+        let metadata = TgsMetadata::deserialize(&animation)
+            .map_err(|e| Error::TgsError(format!("missing lottie header, {}", e)))?;
+        Ok(Self {
+            metadata,
+            animation,
+        })
+    }
+
+    pub fn metadata(&self) -> &TgsMetadata {
+        &self.metadata
+    }
+
+    /// The Lottie JSON document.
+    pub fn animation(&self) -> &Value {
+        &self.animation
+    }
+
+    pub fn into_animation(self) -> Value {
+        self.animation
+    }
+}
+
+/// Decompress a TGS file into Lottie JSON bytes.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    if !is_tgs(data) {
+        return Err(Error::TgsError("not a gzip stream".to_owned()));
+    }
+    let mut json = Vec::new();
+    GzDecoder::new(data)
+        .take(MAX_LOTTIE_SIZE + 1)
+        .read_to_end(&mut json)?;
+    if json.len() as u64 > MAX_LOTTIE_SIZE {
+        return Err(Error::TgsError(
+            "decompressed size exceeds 16 MiB".to_owned(),
+        ));
+    }
+    Ok(json)
+}
+
+/// Check the gzip magic bytes.
+pub fn is_tgs(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_parse_tgs() {
+        let lottie = br#"{"tgs":1,"v":"5.5.2","fr":60,"ip":0,"op":180,"w":512,"h":512,"nm":"duck","layers":[]}"#;
+        let tgs = Tgs::from_bytes(&gzip(lottie)).unwrap();
+        let meta = tgs.metadata();
+        assert_eq!(meta.version, "5.5.2");
+        assert_eq!(meta.frame_rate, 60.0);
+        assert_eq!(meta.frame_count(), 180);
+        assert_eq!(meta.duration(), Duration::from_secs(3));
+        assert_eq!((meta.width, meta.height), (512, 512));
+        assert_eq!(tgs.animation()["nm"], "duck");
+        assert_eq!(decompress(&gzip(lottie)).unwrap(), lottie);
+    }
+
+    #[test]
+    fn test_parse_invalid_tgs() {
+        assert!(Tgs::from_bytes(b"RIFF....WEBP").is_err());
+        assert!(Tgs::from_bytes(&gzip(b"not json")).is_err());
+        assert!(Tgs::from_bytes(&gzip(br#"{"layers":[]}"#)).is_err());
+    }
+}
@@ -0,0 +1,481 @@
+//! Create and edit sticker sets owned by the bot.
+//!
+//! Every method is sent as `multipart/form-data`, so files can be uploaded along
+//! with the request, see [`InputFile`]. [`Bot::clone_sticker_set`] builds on them
+//! to copy any set into a new one owned by the bot.
+
+use std::path::Path;
+
+use bytes::{Bytes, BytesMut};
+use futures_util::TryStreamExt;
+use log::warn;
+use reqwest::multipart::{Form, Part};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    bot::{
+        endpoint, request, Bot, File, MaskPosition, Sticker, StickerFileExt, StickerSet,
+        StickerType, FALLBACK_EMOJI,
+    },
+    error::{Error, Result},
+};
+
+/// Most stickers `createNewStickerSet` accepts, the rest are added one by one.
+pub const MAX_STICKERS_PER_CREATE: usize = 50;
+
+#[derive(Debug, Clone)]
+pub enum InputFile {
+    /// `file_id` of a file already stored by Telegram.
+    FileId(String),
+    /// HTTP URL Telegram downloads the file from.
+    Url(String),
+    /// Content uploaded along with the request.
+    Memory { file_name: String, data: Bytes },
+}
+
+impl InputFile {
+    pub async fn read(path: &Path) -> Result<Self> {
+        let data = tokio::fs::read(path).await?;
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(InputFile::Memory {
+            file_name,
+            data: data.into(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StickerFormat {
+    /// WebP or PNG
+    Static,
+    /// TGS
+    Animated,
+    /// WebM
+    Video,
+}
+
+impl StickerFormat {
+    /// Guess the format by the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "webp" | "png" => Some(StickerFormat::Static),
+            "tgs" => Some(StickerFormat::Animated),
+            "webm" => Some(StickerFormat::Video),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StickerFormat::Static => "static",
+            StickerFormat::Animated => "animated",
+            StickerFormat::Video => "video",
+        }
+    }
+}
+
+impl From<StickerFileExt> for StickerFormat {
+    fn from(ext: StickerFileExt) -> Self {
+        match ext {
+            StickerFileExt::Webp => StickerFormat::Static,
+            StickerFileExt::Tgs => StickerFormat::Animated,
+            StickerFileExt::Webm => StickerFormat::Video,
+        }
+    }
+}
+
+/// A sticker to add to a set.
+#[derive(Debug, Clone)]
+pub struct InputSticker {
+    pub sticker: InputFile,
+    pub format: StickerFormat,
+    /// 1 to 20 emoji associated with the sticker.
+    pub emoji_list: Vec<String>,
+    /// For mask stickers.
+    pub mask_position: Option<MaskPosition>,
+    /// Search keywords for regular and custom emoji stickers.
+    pub keywords: Vec<String>,
+}
+
+impl InputSticker {
+    pub fn new(sticker: InputFile, format: StickerFormat, emoji_list: Vec<String>) -> Self {
+        Self {
+            sticker,
+            format,
+            emoji_list,
+            mask_position: None,
+            keywords: Vec::new(),
+        }
+    }
+
+    /// `sticker` added again by its `file_id`.
+    fn reuse(sticker: &Sticker) -> Self {
+        let emoji = sticker.emoji.as_deref().unwrap_or(FALLBACK_EMOJI);
+        InputSticker {
+            mask_position: sticker.mask_position.clone(),
+            ..InputSticker::new(
+                InputFile::FileId(sticker.file_id.clone()),
+                sticker.file_ext().into(),
+                vec![emoji.to_owned()],
+            )
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CloneReport {
+    /// Name of the new set.
+    pub name: String,
+    /// Stickers added by the `file_id` of the source sticker.
+    pub reused: usize,
+    /// Stickers Telegram refused to reuse, downloaded and uploaded again.
+    pub uploaded: usize,
+}
+
+/// Fields of a multipart request. Kept apart from [`Form`], which can't be cloned,
+/// to build the form again on every retry.
+#[derive(Debug, Default)]
+struct Multipart {
+    text: Vec<(&'static str, String)>,
+    files: Vec<(String, String, Bytes)>,
+}
+
+impl Multipart {
+    fn text(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.text.push((name, value.to_string()));
+        self
+    }
+
+    /// Send `file` as the field `name` itself.
+    fn file(mut self, name: &'static str, file: &InputFile) -> Self {
+        match file {
+            InputFile::FileId(value) | InputFile::Url(value) => {
+                self.text.push((name, value.clone()));
+            }
+            InputFile::Memory { file_name, data } => {
+                self.files
+                    .push((name.to_owned(), file_name.clone(), data.clone()));
+            }
+        }
+        self
+    }
+
+    /// Value referring to `file` from a JSON field, uploading it as a separate part.
+    fn attach(&mut self, file: &InputFile) -> String {
+        match file {
+            InputFile::FileId(value) | InputFile::Url(value) => value.clone(),
+            InputFile::Memory { file_name, data } => {
+                let name = format!("file{}", self.files.len());
+                self.files
+                    .push((name.clone(), file_name.clone(), data.clone()));
+                format!("attach://{}", name)
+            }
+        }
+    }
+
+    fn input_sticker(&mut self, sticker: &InputSticker) -> Value {
+        let mut value = json!({
+            "sticker": self.attach(&sticker.sticker),
+            "format": sticker.format.as_str(),
+            "emoji_list": sticker.emoji_list,
+        });
+        if let Some(mask_position) = &sticker.mask_position {
+            value["mask_position"] = json!(mask_position);
+        }
+        if !sticker.keywords.is_empty() {
+            value["keywords"] = json!(sticker.keywords);
+        }
+        value
+    }
+
+    fn form(&self) -> Form {
+        let mut form = Form::new();
+        for (name, value) in self.text.iter() {
+            form = form.text(*name, value.clone());
+        }
+        for (name, file_name, data) in self.files.iter() {
+            let part = Part::stream(data.clone()).file_name(file_name.clone());
+            form = form.part(name.clone(), part);
+        }
+        form
+    }
+}
+
+/// Methods that have the same effect when Telegram receives them twice.
+fn is_idempotent(method: &str) -> bool {
+    matches!(
+        method,
+        "uploadStickerFile" | "setStickerEmojiList" | "setStickerSetTitle"
+    )
+}
+
+impl Bot {
+    async fn post<T>(&self, method: &str, multipart: Multipart) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let url = endpoint(
+            &self.config.api_url,
+            &[&format!("bot{}", self.config.token), method],
+        );
+        let send = || {
+            request(
+                self.client.post(url.clone()).multipart(multipart.form()),
+                method,
+            )
+        };
+        if is_idempotent(method) {
+            self.config.retry.run(method, send).await
+        } else {
+            self.config.retry.run_unapplied(method, send).await
+        }
+    }
+
+    async fn forget_sticker_set(&self, name: &str) {
+        if let Some(cache) = &self.api_cache {
+            cache.forget_sticker_set(name).await;
+        }
+    }
+
+    /// Name of a set created by this bot, which must end with `_by_<bot username>`.
+    pub fn sticker_set_name(&self, name: &str) -> String {
+        let suffix = format!("_by_{}", self.me().username);
+        if name.to_lowercase().ends_with(&suffix.to_lowercase()) {
+            name.to_owned()
+        } else {
+            format!("{}{}", name, suffix)
+        }
+    }
+
+    /// Upload a file to use in later calls, returning its `file_id`.
+    pub async fn upload_sticker_file(
+        &self,
+        user_id: i64,
+        sticker: &InputFile,
+        format: StickerFormat,
+    ) -> Result<File> {
+        let multipart = Multipart::default()
+            .text("user_id", user_id)
+            .file("sticker", sticker)
+            .text("sticker_format", format.as_str());
+        self.post("uploadStickerFile", multipart).await
+    }
+
+    /// Create a set owned by `user_id` with 1 to [`MAX_STICKERS_PER_CREATE`] stickers.
+    pub async fn create_new_sticker_set(
+        &self,
+        user_id: i64,
+        name: &str,
+        title: &str,
+        sticker_type: &StickerType,
+        stickers: &[InputSticker],
+    ) -> Result<()> {
+        let mut multipart = Multipart::default();
+        let stickers: Vec<Value> = stickers
+            .iter()
+            .map(|sticker| multipart.input_sticker(sticker))
+            .collect();
+        let multipart = multipart
+            .text("user_id", user_id)
+            .text("name", name)
+            .text("title", title)
+            .text("sticker_type", sticker_type)
+            .text("stickers", Value::from(stickers));
+        self.post::<bool>("createNewStickerSet", multipart).await?;
+        self.forget_sticker_set(name).await;
+        Ok(())
+    }
+
+    pub async fn add_sticker_to_set(
+        &self,
+        user_id: i64,
+        name: &str,
+        sticker: &InputSticker,
+    ) -> Result<()> {
+        let mut multipart = Multipart::default();
+        let sticker = multipart.input_sticker(sticker);
+        let multipart = multipart
+            .text("user_id", user_id)
+            .text("name", name)
+            .text("sticker", sticker);
+        self.post::<bool>("addStickerToSet", multipart).await?;
+        self.forget_sticker_set(name).await;
+        Ok(())
+    }
+
+    /// Replace the emoji of a sticker in a set created by the bot.
+    pub async fn set_sticker_emoji_list(&self, file_id: &str, emoji_list: &[String]) -> Result<()> {
+        let emoji_list = serde_json::to_string(emoji_list).map_err(Error::ResponseJsonError)?;
+        let multipart = Multipart::default()
+            .text("sticker", file_id)
+            .text("emoji_list", emoji_list);
+        self.post::<bool>("setStickerEmojiList", multipart).await?;
+        if let Some(cache) = &self.api_cache {
+            cache.forget_sticker_sets().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_sticker_set_title(&self, name: &str, title: &str) -> Result<()> {
+        let multipart = Multipart::default().text("name", name).text("title", title);
+        self.post::<bool>("setStickerSetTitle", multipart).await?;
+        self.forget_sticker_set(name).await;
+        Ok(())
+    }
+
+    pub async fn delete_sticker_set(&self, name: &str) -> Result<()> {
+        let multipart = Multipart::default().text("name", name);
+        self.post::<bool>("deleteStickerSet", multipart).await?;
+        self.forget_sticker_set(name).await;
+        Ok(())
+    }
+
+    /// Delete the set `name` after adding its `added + 1`-th sticker failed with `err`,
+    /// so that the name is free again. Returns `err`, or [`Error::IncompleteSet`] if the
+    /// set could not be deleted.
+    pub async fn abandon_sticker_set(&self, name: &str, added: usize, err: Error) -> Error {
+        match self.delete_sticker_set(name).await {
+            Ok(()) => err,
+            Err(delete_err) => {
+                warn!("fail to delete sticker set {}: {}", name, delete_err);
+                Error::IncompleteSet {
+                    name: name.to_owned(),
+                    added,
+                    source: Box::new(err),
+                }
+            }
+        }
+    }
+
+    /// Copy `source` into a new set `name` owned by `user_id`, keeping the order,
+    /// emoji and mask positions of its stickers.
+    ///
+    /// Stickers are added by their `file_id` first, and downloaded and uploaded again
+    /// when Telegram refuses it. Keywords are not copied, since the Bot API doesn't
+    /// return them. If a sticker can't be added, the new set is deleted, see
+    /// [`Bot::abandon_sticker_set`].
+    pub async fn clone_sticker_set(
+        &self,
+        user_id: i64,
+        source: &StickerSet,
+        name: &str,
+        title: &str,
+    ) -> Result<CloneReport> {
+        let name = self.sticker_set_name(name);
+        if source.stickers.is_empty() {
+            return Err(Error::Api {
+                code: 400,
+                description: format!("Bad Request: sticker set {} is empty", source.name),
+                retry_after: None,
+                migrate_to_chat_id: None,
+            });
+        }
+        // Fail early rather than upload stickers again for an occupied name
+        match self.request_sticker_set(&name).await {
+            Ok(_) => {
+                return Err(Error::Api {
+                    code: 400,
+                    description: "Bad Request: sticker set name is already occupied".to_owned(),
+                    retry_after: None,
+                    migrate_to_chat_id: None,
+                })
+            }
+            Err(e) if e.is_not_found() => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut report = CloneReport {
+            name: name.clone(),
+            ..Default::default()
+        };
+        // Created with the first sticker alone, so that a refused file_id only costs
+        // uploading that sticker again rather than the whole batch.
+        let (first, rest) = source.stickers.split_first().unwrap();
+        let kind = &source.sticker_type;
+        match self
+            .create_new_sticker_set(user_id, &name, title, kind, &[InputSticker::reuse(first)])
+            .await
+        {
+            Ok(()) => report.reused += 1,
+            Err(e) if is_refused(&e) => {
+                let input = self.reupload(user_id, first).await?;
+                self.create_new_sticker_set(user_id, &name, title, kind, &[input])
+                    .await?;
+                report.uploaded += 1;
+            }
+            Err(e) => return Err(e),
+        }
+
+        for (idx, sticker) in rest.iter().enumerate() {
+            if let Err(e) = self.clone_sticker(user_id, sticker, &mut report).await {
+                return Err(self.abandon_sticker_set(&name, 1 + idx, e).await);
+            }
+        }
+        Ok(report)
+    }
+
+    async fn clone_sticker(
+        &self,
+        user_id: i64,
+        sticker: &Sticker,
+        report: &mut CloneReport,
+    ) -> Result<()> {
+        match self
+            .add_sticker_to_set(user_id, &report.name, &InputSticker::reuse(sticker))
+            .await
+        {
+            Ok(()) => report.reused += 1,
+            Err(e) if is_refused(&e) => {
+                let input = self.reupload(user_id, sticker).await?;
+                self.add_sticker_to_set(user_id, &report.name, &input)
+                    .await?;
+                report.uploaded += 1;
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    async fn reupload(&self, user_id: i64, sticker: &Sticker) -> Result<InputSticker> {
+        let path = self.request_file_id(sticker).await?;
+        let data: BytesMut = self.download_file(&path).await?.try_collect().await?;
+        let file = InputFile::Memory {
+            file_name: format!("{}.{}", sticker.file_unique_id, sticker.file_ext()),
+            data: data.freeze(),
+        };
+        let format = StickerFormat::from(sticker.file_ext());
+        let uploaded = self.upload_sticker_file(user_id, &file, format).await?;
+        Ok(InputSticker {
+            sticker: InputFile::FileId(uploaded.file_id),
+            ..InputSticker::reuse(sticker)
+        })
+    }
+}
+
+/// Telegram won't reuse the `file_id`, e.g. a file of another bot.
+fn is_refused(err: &Error) -> bool {
+    const REFUSED: &[&str] = &[
+        "wrong file identifier",
+        "wrong remote file identifier",
+        "invalid file_id",
+        "sticker_file_invalid",
+        "file_reference",
+    ];
+    match err {
+        Error::Api {
+            code: 400,
+            description,
+            ..
+        } => {
+            let description = description.to_lowercase();
+            REFUSED.iter().any(|refused| description.contains(refused))
+        }
+        _ => false,
+    }
+}
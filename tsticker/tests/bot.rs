@@ -0,0 +1,340 @@
+use std::time::Duration;
+
+use futures_util::TryStreamExt;
+use serde_json::Value;
+use tsticker::{
+    api_cache::ApiCache,
+    bot::{Bot, StickerFileExt, TelegramFile, TelegramFileID},
+    error::Error,
+    mock::MockServer,
+    retry::RetryPolicy,
+};
+
+fn fixture(name: &str) -> Value {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+async fn read_all(bot: &Bot, path: &TelegramFileID) -> Vec<u8> {
+    bot.download_file(path)
+        .await
+        .unwrap()
+        .try_fold(Vec::new(), |mut acc, chunk| async move {
+            acc.extend_from_slice(&chunk);
+            Ok(acc)
+        })
+        .await
+        .unwrap()
+}
+
+async fn setup() -> (MockServer, Bot) {
+    setup_with(RetryPolicy::default()).await
+}
+
+async fn setup_with(retry: RetryPolicy) -> (MockServer, Bot) {
+    let server = MockServer::start().await;
+    server.add_sticker_set(fixture("static_set.json"));
+    server.add_sticker_set(fixture("video_set.json"));
+    let bot = Bot::builder(server.token().to_owned())
+        .api_url(server.url())
+        .retry_policy(retry)
+        .login()
+        .await
+        .expect("Fail to login");
+    (server, bot)
+}
+
+#[tokio::test]
+async fn test_login() {
+    let (server, bot) = setup().await;
+    assert_eq!(bot.me().username, "mock_bot");
+    assert_eq!(server.hits("getMe"), 1);
+}
+
+#[tokio::test]
+async fn test_login_fail() {
+    let server = MockServer::start().await;
+    let res = Bot::builder("000000:AAAAAAAAAA".to_owned())
+        .api_url(server.url())
+        .login()
+        .await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn test_list_sticker() {
+    let (_server, bot) = setup().await;
+    let test_list = [
+        ("myadestes_1_amashiro_natsuki_plus_nacho_neko", 4), // plain
+        ("in_EDIHDC_by_NaiDrawBot", 3),                      // video
+    ];
+    for (name, len) in test_list {
+        let sticker_set = bot.request_sticker_set(name).await.unwrap();
+        assert_eq!(sticker_set.name, name);
+        assert_eq!(sticker_set.stickers.len(), len);
+    }
+}
+
+#[tokio::test]
+async fn test_list_sticker_not_found() {
+    let (_server, bot) = setup().await;
+    assert!(bot.request_sticker_set("not_exists").await.is_err());
+}
+
+#[tokio::test]
+async fn test_download_file() {
+    let (server, bot) = setup().await;
+    let set = bot
+        .request_sticker_set("in_EDIHDC_by_NaiDrawBot")
+        .await
+        .unwrap();
+    let sticker = &set.stickers[0];
+
+    let path = bot.request_file_id(sticker).await.unwrap();
+    assert_eq!(
+        path.to_string(),
+        format!("stickers/{}", sticker.file_unique_id)
+    );
+
+    let content = read_all(&bot, &path).await;
+    assert_eq!(content, sticker.file_unique_id.as_bytes());
+    assert_eq!(server.hits("getFile"), 1);
+    assert_eq!(server.hits("file"), 1);
+}
+
+#[tokio::test]
+async fn test_download_thumbnail() {
+    let (_server, bot) = setup().await;
+    let set = bot
+        .request_sticker_set("myadestes_1_amashiro_natsuki_plus_nacho_neko")
+        .await
+        .unwrap();
+    let thumb = set.stickers[1].thumbnail().unwrap();
+    let path = bot.request_file_id(thumb).await.unwrap();
+    let content = read_all(&bot, &path).await;
+    assert_eq!(content, thumb.file_unique_id.as_bytes());
+    assert_ne!(thumb.file_id(), set.stickers[1].file_id());
+}
+
+#[tokio::test]
+async fn test_local_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("sticker.webp");
+    std::fs::write(&local, b"local content").unwrap();
+
+    let server = MockServer::start().await;
+    let set = fixture("static_set.json");
+    server.add_sticker_set(set.clone());
+    let file_id = set["stickers"][0]["file_id"].as_str().unwrap();
+    server.add_local_file(file_id, "local", &local);
+
+    let bot = Bot::builder(server.token().to_owned())
+        .api_url(server.url())
+        .local_mode(true)
+        .login()
+        .await
+        .unwrap();
+    let set = bot
+        .request_sticker_set("myadestes_1_amashiro_natsuki_plus_nacho_neko")
+        .await
+        .unwrap();
+    let path = bot.request_file_id(&set.stickers[0]).await.unwrap();
+    let content = read_all(&bot, &path).await;
+    assert_eq!(content, b"local content");
+    assert_eq!(server.hits("file"), 0);
+}
+
+#[tokio::test]
+async fn test_error_responses() {
+    let (server, bot) = setup_with(RetryPolicy::none()).await;
+    let set = bot
+        .request_sticker_set("in_EDIHDC_by_NaiDrawBot")
+        .await
+        .unwrap();
+
+    server.fail("getFile", 400, "Bad Request: wrong file_id", None);
+    let err = bot.request_file_id(&set.stickers[0]).await.unwrap_err();
+    assert!(matches!(err, Error::Api { code: 400, .. }));
+
+    server.fail("getStickerSet", 401, "Unauthorized", None);
+    let err = bot
+        .request_sticker_set("in_EDIHDC_by_NaiDrawBot")
+        .await
+        .unwrap_err();
+    assert!(err.is_unauthorized());
+    assert!(!err.is_not_found());
+
+    server.fail("getFile", 429, "Too Many Requests: retry after 1", Some(1));
+    let err = bot.request_file_id(&set.stickers[0]).await.unwrap_err();
+    assert!(err.is_rate_limited());
+    assert_eq!(err.retry_after(), Some(Duration::from_secs(1)));
+
+    let path = bot.request_file_id(&set.stickers[0]).await.unwrap();
+    server.fail("file", 404, "Not Found", None);
+    let err = bot.download_file(&path).await.err().unwrap();
+    assert!(err.is_not_found());
+    // Errors end up in logs and reports, so they must not leak the token
+    assert!(!err.to_string().contains(server.token()));
+}
+
+#[tokio::test]
+async fn test_error_not_found() {
+    let (_server, bot) = setup().await;
+    let err = bot.request_sticker_set("not_exists").await.unwrap_err();
+    assert!(err.is_not_found());
+    assert!(!err.is_unauthorized());
+    assert!(!err.is_rate_limited());
+    match err {
+        Error::Api {
+            code, description, ..
+        } => {
+            assert_eq!(code, 400);
+            assert_eq!(description, "Bad Request: STICKERSET_INVALID");
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[tokio::test]
+async fn test_sticker_file_ext() {
+    let server = MockServer::start().await;
+    server.add_sticker_set(fixture("static_set.json"));
+    server.add_sticker_set(fixture("video_set.json"));
+    server.add_sticker_set(fixture("animated_set.json"));
+    let bot = Bot::builder(server.token().to_owned())
+        .api_url(server.url())
+        .login()
+        .await
+        .unwrap();
+    let cases = [
+        (
+            "myadestes_1_amashiro_natsuki_plus_nacho_neko",
+            StickerFileExt::Webp,
+        ),
+        ("in_EDIHDC_by_NaiDrawBot", StickerFileExt::Webm),
+        ("AnimatedDuck", StickerFileExt::Tgs),
+    ];
+    for (name, ext) in cases {
+        let set = bot.request_sticker_set(name).await.unwrap();
+        assert!(set.stickers.iter().all(|s| s.file_ext() == ext));
+    }
+}
+
+#[tokio::test]
+async fn test_retry() {
+    let (server, bot) = setup_with(RetryPolicy {
+        base_delay: Duration::from_millis(10),
+        ..Default::default()
+    })
+    .await;
+    let set = bot
+        .request_sticker_set("in_EDIHDC_by_NaiDrawBot")
+        .await
+        .unwrap();
+
+    server.fail("getFile", 429, "Too Many Requests: retry after 0", Some(0));
+    server.fail("getFile", 502, "Bad Gateway", None);
+    let path = bot.request_file_id(&set.stickers[0]).await.unwrap();
+    assert_eq!(server.hits("getFile"), 3);
+
+    server.fail("file", 503, "Service Unavailable", None);
+    let content = read_all(&bot, &path).await;
+    assert_eq!(content, set.stickers[0].file_unique_id.as_bytes());
+    assert_eq!(server.hits("file"), 2);
+
+    for _ in 0..4 {
+        server.fail("getFile", 500, "Internal Server Error", None);
+    }
+    let err = bot.request_file_id(&set.stickers[0]).await.unwrap_err();
+    assert!(matches!(err, Error::Api { code: 500, .. }));
+    assert_eq!(server.hits("getFile"), 7);
+
+    server.fail("getFile", 400, "Bad Request: wrong file_id", None);
+    assert!(bot.request_file_id(&set.stickers[0]).await.is_err());
+    assert_eq!(server.hits("getFile"), 8);
+}
+
+#[tokio::test]
+async fn test_custom_emoji_stickers() {
+    let (server, bot) = setup().await;
+    server.add_sticker_set(fixture("custom_emoji_set.json"));
+
+    let mut ids: Vec<String> = (0..250).map(|i| format!("{}", 1000 + i)).collect();
+    ids[3] = "5368324170671202287".to_owned();
+    ids[240] = "5368324170671202286".to_owned();
+    let stickers = bot.get_custom_emoji_stickers(&ids).await.unwrap();
+    assert_eq!(server.hits("getCustomEmojiStickers"), 2);
+    let found: Vec<_> = stickers
+        .iter()
+        .map(|s| s.custom_emoji_id.as_deref().unwrap())
+        .collect();
+    assert_eq!(found, ["5368324170671202287", "5368324170671202286"]);
+
+    let path = bot.request_file_id(&stickers[0]).await.unwrap();
+    assert_eq!(read_all(&bot, &path).await, b"AgADPixel01");
+}
+
+#[tokio::test]
+async fn test_api_cache() {
+    let server = MockServer::start().await;
+    server.add_sticker_set(fixture("static_set.json"));
+    let dir = tempfile::tempdir().unwrap();
+    let login = |cache: ApiCache| {
+        Bot::builder(server.token().to_owned())
+            .api_url(server.url())
+            .api_cache(cache)
+            .login()
+    };
+    let name = "myadestes_1_amashiro_natsuki_plus_nacho_neko";
+
+    let bot = login(ApiCache::on_disk(dir.path().to_owned()))
+        .await
+        .unwrap();
+    let set = bot.request_sticker_set(name).await.unwrap();
+    let path = bot.request_file_id(&set.stickers[0]).await.unwrap();
+    bot.request_sticker_set(&name.to_uppercase()).await.unwrap();
+    bot.request_file_id(&set.stickers[0]).await.unwrap();
+    assert_eq!(server.hits("getStickerSet"), 1);
+    assert_eq!(server.hits("getFile"), 1);
+
+    // A later run reads the cache from disk
+    let bot = login(ApiCache::on_disk(dir.path().to_owned()))
+        .await
+        .unwrap();
+    let cached = bot.request_sticker_set(name).await.unwrap();
+    assert_eq!(cached.fingerprint(), set.fingerprint());
+    assert!(bot
+        .request_sticker_set_if_changed(name, &set.fingerprint())
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        bot.request_file_id(&set.stickers[0])
+            .await
+            .unwrap()
+            .to_string(),
+        path.to_string()
+    );
+    assert_eq!(server.hits("getStickerSet"), 1);
+    assert_eq!(server.hits("getFile"), 1);
+
+    let cache = ApiCache::on_disk(dir.path().to_owned())
+        .with_set_ttl(Duration::ZERO)
+        .with_file_ttl(Duration::ZERO);
+    let bot = login(cache.clone()).await.unwrap();
+    let changed = bot
+        .request_sticker_set_if_changed(name, "0000000000000000")
+        .await
+        .unwrap();
+    assert_eq!(changed.unwrap().fingerprint(), set.fingerprint());
+    bot.request_file_id(&set.stickers[0]).await.unwrap();
+    assert_eq!(server.hits("getStickerSet"), 2);
+    assert_eq!(server.hits("getFile"), 2);
+
+    cache.clear().await.unwrap();
+    let bot = login(ApiCache::on_disk(dir.path().to_owned()))
+        .await
+        .unwrap();
+    bot.request_sticker_set(name).await.unwrap();
+    assert_eq!(server.hits("getStickerSet"), 3);
+}
@@ -0,0 +1,348 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
+use serde_json::Value;
+use tsticker::{
+    bot::Bot,
+    cache::StickerCache,
+    download::{DownloadEvent, DownloadOptions, Downloader, OverwritePolicy},
+    error::Error,
+    manifest::Manifest,
+    mock::MockServer,
+    sanitize::SetFolder,
+    template::NameTemplate,
+};
+
+fn fixture(name: &str) -> Value {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+async fn setup() -> (MockServer, Bot) {
+    let server = MockServer::start().await;
+    server.add_sticker_set(fixture("static_set.json"));
+    server.add_sticker_set(fixture("video_set.json"));
+    let bot = Bot::builder(server.token().to_owned())
+        .api_url(server.url())
+        .login()
+        .await
+        .unwrap();
+    (server, bot)
+}
+
+async fn run(bot: &Bot, names: &[&str], options: DownloadOptions) -> Vec<DownloadEvent> {
+    let mut sets = Vec::new();
+    for name in names {
+        sets.push(bot.request_sticker_set(name).await.unwrap());
+    }
+    Downloader::new(bot.clone(), sets, options)
+        .run()
+        .collect()
+        .await
+}
+
+/// `(downloaded, skipped, failed)` of each set, in the order of `names`.
+fn finished(events: &[DownloadEvent], names: &[&str]) -> Vec<(usize, usize, usize)> {
+    names
+        .iter()
+        .map(|name| {
+            events
+                .iter()
+                .find_map(|e| match e {
+                    DownloadEvent::SetFinished {
+                        set,
+                        downloaded,
+                        skipped,
+                        failed,
+                    } if set == name => Some((*downloaded, *skipped, *failed)),
+                    _ => None,
+                })
+                .unwrap()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_downloader_events() {
+    let (server, bot) = setup().await;
+    let dir = tempfile::tempdir().unwrap();
+    let names = [
+        "myadestes_1_amashiro_natsuki_plus_nacho_neko",
+        "in_EDIHDC_by_NaiDrawBot",
+    ];
+
+    server.fail("getFile", 400, "Bad Request: wrong file_id", None);
+    let events = run(
+        &bot,
+        &names[..1],
+        DownloadOptions::new(dir.path().to_owned()),
+    )
+    .await;
+    assert!(matches!(
+        events[0],
+        DownloadEvent::SetStarted { total: 4, .. }
+    ));
+    assert_eq!(finished(&events, &names[..1]), vec![(3, 0, 1)]);
+    for event in events.iter() {
+        if let DownloadEvent::StickerDownloaded {
+            sticker,
+            path,
+            size,
+            ..
+        } = event
+        {
+            assert_eq!(
+                std::fs::read(path).unwrap(),
+                sticker.file_unique_id.as_bytes()
+            );
+            assert_eq!(*size, sticker.file_unique_id.len() as u64);
+        }
+    }
+
+    let events = run(&bot, &names, DownloadOptions::new(dir.path().to_owned())).await;
+    assert_eq!(finished(&events, &names), vec![(1, 3, 0), (3, 0, 0)]);
+
+    let options = DownloadOptions {
+        overwrite: OverwritePolicy::Overwrite,
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    let events = run(&bot, &names, options).await;
+    assert_eq!(finished(&events, &names), vec![(4, 0, 0), (3, 0, 0)]);
+}
+
+#[tokio::test]
+async fn test_downloader_jobs() {
+    let (server, bot) = setup().await;
+    server.set_latency(Duration::from_millis(50));
+    let names = [
+        "myadestes_1_amashiro_natsuki_plus_nacho_neko",
+        "in_EDIHDC_by_NaiDrawBot",
+    ];
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        jobs: 3,
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    let events = run(&bot, &names, options).await;
+    assert_eq!(finished(&events, &names), vec![(4, 0, 0), (3, 0, 0)]);
+    assert_eq!(server.max_in_flight(), 3);
+
+    let (server, bot) = setup().await;
+    server.set_latency(Duration::from_millis(20));
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        jobs: 8,
+        jobs_per_set: Some(1),
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    run(&bot, &names[..1], options).await;
+    assert_eq!(server.max_in_flight(), 1);
+}
+
+#[tokio::test]
+async fn test_downloader_keep_existing() {
+    let (_server, bot) = setup().await;
+    let dir = tempfile::tempdir().unwrap();
+    let set_dir = dir.path().join("NaiDraw EDIHDC");
+    std::fs::create_dir_all(&set_dir).unwrap();
+    std::fs::write(set_dir.join("0.webm"), b"mine").unwrap();
+
+    let options = DownloadOptions {
+        naming: Arc::new(|_, index, sticker| format!("{}.{}", index, sticker.file_ext())),
+        overwrite: OverwritePolicy::KeepExisting,
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    let events = run(&bot, &["in_EDIHDC_by_NaiDrawBot"], options).await;
+    assert_eq!(
+        finished(&events, &["in_EDIHDC_by_NaiDrawBot"]),
+        vec![(2, 1, 0)]
+    );
+    assert_eq!(std::fs::read(set_dir.join("0.webm")).unwrap(), b"mine");
+    assert!(set_dir.join("2.webm").exists());
+}
+
+#[tokio::test]
+async fn test_downloader_name_template() {
+    let (server, bot) = setup().await;
+    let dir = tempfile::tempdir().unwrap();
+    let template: NameTemplate = "{format}/{index:02}_{width}x{height}.{ext}"
+        .parse()
+        .unwrap();
+    let options = DownloadOptions {
+        naming: template.clone().naming(),
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    let events = run(&bot, &["in_EDIHDC_by_NaiDrawBot"], options).await;
+    assert_eq!(
+        finished(&events, &["in_EDIHDC_by_NaiDrawBot"]),
+        vec![(3, 0, 0)]
+    );
+    let set_dir = dir.path().join("NaiDraw EDIHDC");
+    assert!(set_dir.join("video/00_512x512.webm").exists());
+
+    // Synced files are found again in the sub directory.
+    let options = DownloadOptions {
+        naming: template.naming(),
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    let events = run(&bot, &["in_EDIHDC_by_NaiDrawBot"], options).await;
+    assert_eq!(
+        finished(&events, &["in_EDIHDC_by_NaiDrawBot"]),
+        vec![(0, 3, 0)]
+    );
+
+    let options = DownloadOptions {
+        naming: Arc::new(|_, _, sticker| format!("Same.{}", sticker.file_ext())),
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    let events = run(&bot, &["in_EDIHDC_by_NaiDrawBot"], options).await;
+    assert!(events.iter().any(|e| matches!(
+        e,
+        DownloadEvent::SetFailed {
+            error: Error::NameCollision { file_name, .. },
+            ..
+        } if file_name == "Same.webm"
+    )));
+    assert!(!set_dir.join("Same.webm").exists());
+
+    // Synced files are renamed after the template, not downloaded again.
+    let hits = server.hits("getFile");
+    let template: NameTemplate = "{index:02}_{unique_id}.{ext}".parse().unwrap();
+    let options = DownloadOptions {
+        name_template: Some(template),
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    let events = run(&bot, &["in_EDIHDC_by_NaiDrawBot"], options).await;
+    assert_eq!(
+        finished(&events, &["in_EDIHDC_by_NaiDrawBot"]),
+        vec![(0, 3, 0)]
+    );
+    assert_eq!(server.hits("getFile"), hits);
+    assert!(!set_dir.join("video/00_512x512.webm").exists());
+    let manifest = Manifest::load(&set_dir).await.unwrap();
+    assert_eq!(
+        manifest.name_template.as_deref(),
+        Some("{index:02}_{unique_id}.{ext}")
+    );
+    for (id, entry) in manifest.stickers.iter() {
+        assert!(entry.file_name.ends_with(&format!("_{}.webm", id)));
+        assert!(set_dir.join(&entry.file_name).exists());
+    }
+}
+
+#[tokio::test]
+async fn test_downloader_sanitize() {
+    let server = MockServer::start().await;
+    let mut set = fixture("static_set.json");
+    set["title"] = Value::from("../a/b: c.");
+    server.add_sticker_set(set);
+    let bot = Bot::builder(server.token().to_owned())
+        .api_url(server.url())
+        .login()
+        .await
+        .unwrap();
+    let name = "myadestes_1_amashiro_natsuki_plus_nacho_neko";
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        naming: Arc::new(|_, index, sticker| {
+            format!("../{}:{}.{}", index, index, sticker.file_ext())
+        }),
+        ..DownloadOptions::new(dir.path().join("out"))
+    };
+    let events = run(&bot, &[name], options).await;
+    assert_eq!(finished(&events, &[name]), vec![(4, 0, 0)]);
+    assert!(dir.path().join("out/.._a_b_ c/_/0_0.webp").exists());
+
+    let options = DownloadOptions {
+        folder: SetFolder::Name,
+        ..DownloadOptions::new(dir.path().join("out"))
+    };
+    let events = run(&bot, &[name], options).await;
+    assert_eq!(finished(&events, &[name]), vec![(4, 0, 0)]);
+    assert!(dir.path().join("out").join(name).is_dir());
+}
+
+#[tokio::test]
+async fn test_downloader_verify() {
+    let (server, bot) = setup().await;
+    let name = "myadestes_1_amashiro_natsuki_plus_nacho_neko";
+    let set = bot.request_sticker_set(name).await.unwrap();
+    // The files change after the set is fetched, as if the connection dropped.
+    let broken = &set.stickers[0];
+    server.add_file(&broken.file_id, &broken.file_unique_id, "short");
+    let webp = &set.stickers[1];
+    let mut content = b"RIFF\0\0\0\0WEBP".to_vec();
+    content.resize(webp.file_size.unwrap() as usize, 0);
+    server.add_file(&webp.file_id, &webp.file_unique_id, content);
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        naming: Arc::new(|_, index, sticker| format!("{}.{}", index, sticker.file_ext())),
+        check_format: true,
+        ..DownloadOptions::new(dir.path().to_owned())
+    };
+    let events: Vec<DownloadEvent> = Downloader::new(bot.clone(), vec![set.clone()], options)
+        .run()
+        .collect()
+        .await;
+    assert_eq!(finished(&events, &[name]), vec![(1, 0, 3)]);
+    let errors: Vec<&Error> = events
+        .iter()
+        .filter_map(|e| match e {
+            DownloadEvent::StickerFailed { error, .. } => Some(error),
+            _ => None,
+        })
+        .collect();
+    assert!(errors
+        .iter()
+        .any(|e| matches!(e, Error::SizeMismatch { actual: 5, .. })));
+    assert!(errors
+        .iter()
+        .any(|e| matches!(e, Error::InvalidFileFormat { .. })));
+
+    let set_dir = dir.path().join(&set.title);
+    let mut files: Vec<String> = std::fs::read_dir(&set_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.ends_with(".json"))
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["1.webp"]);
+}
+
+#[tokio::test]
+async fn test_downloader_cache() {
+    let (server, bot) = setup().await;
+    let name = "in_EDIHDC_by_NaiDrawBot";
+    let dir = tempfile::tempdir().unwrap();
+    let cache = StickerCache::new(dir.path().join("cache"));
+    let options = |dst: &str| DownloadOptions {
+        cache: Some(cache.clone()),
+        ..DownloadOptions::new(dir.path().join(dst))
+    };
+
+    let events = run(&bot, &[name], options("first")).await;
+    assert_eq!(finished(&events, &[name]), vec![(3, 0, 0)]);
+    assert_eq!(server.hits("file"), 3);
+    assert_eq!(cache.entries().await.unwrap().len(), 3);
+
+    // Another destination is filled from the cache without touching Telegram.
+    let events = run(&bot, &[name], options("second")).await;
+    assert_eq!(finished(&events, &[name]), vec![(3, 0, 0)]);
+    assert_eq!(server.hits("file"), 3);
+    assert!(events
+        .iter()
+        .all(|e| !matches!(e, DownloadEvent::StickerDownloaded { cached: false, .. })));
+    let set_dir = dir.path().join("second/NaiDraw EDIHDC");
+    assert_eq!(std::fs::read_dir(set_dir).unwrap().count(), 3 + 2);
+
+    let cache = cache.with_max_size(0);
+    let options = DownloadOptions {
+        cache: Some(cache.clone()),
+        ..DownloadOptions::new(dir.path().join("third"))
+    };
+    run(&bot, &[name], options).await;
+    assert!(cache.entries().await.unwrap().is_empty());
+}
@@ -0,0 +1,121 @@
+use tsticker::bot::{MaskPoint, StickerFileExt, StickerSet, StickerType, TelegramFile};
+
+fn fixture(name: &str) -> StickerSet {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_fixtures() {
+    for name in [
+        "static_set.json",
+        "video_set.json",
+        "animated_set.json",
+        "mask_set.json",
+        "custom_emoji_set.json",
+        "premium_set.json",
+    ] {
+        let set = fixture(name);
+        assert!(!set.stickers.is_empty(), "{}", name);
+    }
+}
+
+#[test]
+fn test_mask_set() {
+    let set = fixture("mask_set.json");
+    assert_eq!(set.sticker_type, StickerType::Mask);
+    assert!(set.stickers.iter().all(|s| s.ty == StickerType::Mask));
+
+    let mask = set.stickers[0].mask_position.as_ref().unwrap();
+    assert_eq!(mask.point, MaskPoint::Eyes);
+    assert_eq!((mask.x_shift, mask.y_shift, mask.scale), (-0.25, 0.1, 1.5));
+
+    // optional fields may all be missing
+    let sticker = &set.stickers[1];
+    assert!(sticker.emoji.is_none());
+    assert!(sticker.thumbnail().is_none());
+    assert!(sticker.set_name.is_none());
+    assert_eq!(sticker.file_size(), None);
+    assert_eq!(
+        sticker.mask_position.as_ref().unwrap().point,
+        MaskPoint::Mouth
+    );
+}
+
+#[test]
+fn test_custom_emoji_set() {
+    let set = fixture("custom_emoji_set.json");
+    assert_eq!(set.sticker_type, StickerType::CustomEmoji);
+    assert_eq!(set.thumbnail.unwrap().width, 100);
+
+    let [repainted, plain] = &set.stickers[..] else {
+        panic!("expect 2 stickers");
+    };
+    assert_eq!(
+        repainted.custom_emoji_id.as_deref(),
+        Some("5368324170671202286")
+    );
+    assert!(repainted.needs_repainting);
+    assert!(!plain.needs_repainting);
+    assert_eq!(plain.file_ext(), StickerFileExt::Webm);
+    assert!(plain.mask_position.is_none());
+
+    let mut both = plain.clone();
+    both.is_animated = true;
+    assert_eq!(both.file_ext(), StickerFileExt::Webm);
+}
+
+#[test]
+fn test_premium_set() {
+    let set = fixture("premium_set.json");
+    let sticker = &set.stickers[0];
+    let premium = sticker.premium_animation.as_ref().unwrap();
+    assert_eq!(premium.file_unique_id, "AgADPremium00fx");
+    assert_eq!(premium.file_size(), Some(41523));
+    assert!(premium.file_path.is_none());
+
+    // the legacy `thumb` is used when `thumbnail` is missing
+    assert_eq!(sticker.thumbnail().unwrap().file_unique_id, "AQADPremium00");
+    assert_eq!(sticker.emoji.as_deref(), Some("😼"));
+}
+
+#[test]
+fn test_sticker_type() {
+    let set = serde_json::json!({
+        "name": "future",
+        "title": "Future",
+        "sticker_type": "hologram",
+        "stickers": [],
+    });
+    let set: StickerSet = serde_json::from_value(set).unwrap();
+    assert_eq!(
+        set.sticker_type,
+        StickerType::Unknown("hologram".to_owned())
+    );
+    assert_eq!(set.sticker_type.to_string(), "hologram");
+
+    assert_eq!(
+        "custom_emoji".parse::<StickerType>(),
+        Ok(StickerType::CustomEmoji)
+    );
+    assert!("hologram".parse::<StickerType>().is_err());
+}
+
+#[test]
+fn test_fingerprint() {
+    let set = fixture("static_set.json");
+    let fingerprint = set.fingerprint();
+    assert_eq!(fingerprint.len(), 16);
+
+    let mut same = set.clone();
+    same.stickers[0].file_id = "another_file_id".to_owned();
+    assert_eq!(same.fingerprint(), fingerprint);
+
+    let mut emoji = set.clone();
+    emoji.stickers[0].emoji = Some("🐈".to_owned());
+    assert_ne!(emoji.fingerprint(), fingerprint);
+
+    let mut reordered = set.clone();
+    reordered.stickers.swap(0, 1);
+    assert_ne!(reordered.fingerprint(), fingerprint);
+}
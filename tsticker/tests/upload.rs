@@ -0,0 +1,204 @@
+use tsticker::{
+    api_cache::ApiCache,
+    bot::{Bot, StickerType},
+    mock::MockServer,
+    upload::{InputFile, InputSticker, StickerFormat},
+};
+
+async fn setup() -> (MockServer, Bot) {
+    let server = MockServer::start().await;
+    // Sets changed by the bot must not be served from the cache
+    let bot = Bot::builder(server.token().to_owned())
+        .api_url(server.url())
+        .api_cache(ApiCache::new())
+        .login()
+        .await
+        .unwrap();
+    (server, bot)
+}
+
+fn memory(name: &str) -> InputFile {
+    InputFile::Memory {
+        file_name: format!("{}.webp", name),
+        data: name.as_bytes().to_vec().into(),
+    }
+}
+
+#[tokio::test]
+async fn test_create_sticker_set() {
+    let (server, bot) = setup().await;
+    let name = bot.sticker_set_name("cats");
+    assert_eq!(name, "cats_by_mock_bot");
+    assert_eq!(bot.sticker_set_name(&name), name);
+
+    let uploaded = bot
+        .upload_sticker_file(42, &memory("first"), StickerFormat::Static)
+        .await
+        .unwrap();
+    let stickers = [
+        InputSticker::new(
+            InputFile::FileId(uploaded.file_id),
+            StickerFormat::Static,
+            vec!["🐱".to_owned()],
+        ),
+        InputSticker::new(
+            memory("second"),
+            StickerFormat::Static,
+            vec!["😺".to_owned()],
+        ),
+    ];
+    bot.create_new_sticker_set(42, &name, "Cats", &StickerType::Regular, &stickers)
+        .await
+        .unwrap();
+    let err = bot
+        .create_new_sticker_set(42, &name, "Cats", &StickerType::Regular, &stickers)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("already occupied"));
+
+    let mut video = InputSticker::new(memory("third"), StickerFormat::Video, vec!["😹".to_owned()]);
+    video.keywords = vec!["laugh".to_owned()];
+    // A failed addStickerToSet may have been applied, so only rate limits are retried
+    server.fail("addStickerToSet", 502, "Bad Gateway", None);
+    assert!(bot.add_sticker_to_set(42, &name, &video).await.is_err());
+    assert_eq!(server.hits("addStickerToSet"), 1);
+    server.fail(
+        "addStickerToSet",
+        429,
+        "Too Many Requests: retry after 0",
+        Some(0),
+    );
+    bot.add_sticker_to_set(42, &name, &video).await.unwrap();
+    assert_eq!(server.hits("addStickerToSet"), 3);
+
+    let set = bot.request_sticker_set(&name).await.unwrap();
+    assert_eq!(set.title, "Cats");
+    assert_eq!(set.stickers.len(), 3);
+    assert!(set.stickers[2].is_video);
+    let path = bot.request_file_id(&set.stickers[1]).await.unwrap();
+    let content = tsticker::download::download_sticker(
+        &bot,
+        &path,
+        &tempfile::tempdir().unwrap().path().join("second.webp"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(content, "second".len() as u64);
+
+    bot.set_sticker_emoji_list(&set.stickers[0].file_id, &["🐈".to_owned()])
+        .await
+        .unwrap();
+    bot.set_sticker_set_title(&name, "More cats").await.unwrap();
+    let set = bot.request_sticker_set(&name).await.unwrap();
+    assert_eq!(set.title, "More cats");
+    assert_eq!(set.stickers[0].emoji.as_deref(), Some("🐈"));
+
+    bot.delete_sticker_set(&name).await.unwrap();
+    assert!(bot
+        .request_sticker_set(&name)
+        .await
+        .unwrap_err()
+        .is_not_found());
+    assert_eq!(server.hits("createNewStickerSet"), 2);
+}
+
+#[tokio::test]
+async fn test_clone_sticker_set() {
+    let (server, bot) = setup().await;
+    let stickers: Vec<serde_json::Value> = (0..52)
+        .map(|idx| {
+            serde_json::json!({
+                "file_id": format!("source{}", idx),
+                "file_unique_id": format!("AgADSource{}", idx),
+                "type": "regular",
+                "width": 512,
+                "height": 512,
+                "is_animated": false,
+                "is_video": idx == 51,
+                "emoji": if idx == 0 { "🐱" } else { "😺" },
+                "set_name": "Source",
+            })
+        })
+        .collect();
+    server.add_sticker_set(serde_json::json!({
+        "name": "Source",
+        "title": "Source",
+        "sticker_type": "regular",
+        "stickers": stickers,
+    }));
+    let source = bot.request_sticker_set("Source").await.unwrap();
+
+    server.fail(
+        "createNewStickerSet",
+        400,
+        "Bad Request: STICKER_FILE_INVALID",
+        None,
+    );
+    server.fail(
+        "addStickerToSet",
+        400,
+        "Bad Request: STICKER_FILE_INVALID",
+        None,
+    );
+    let report = bot
+        .clone_sticker_set(42, &source, "copy", "Copy")
+        .await
+        .unwrap();
+    // Only the two refused stickers are uploaded again, although both would have
+    // been in the same createNewStickerSet batch
+    assert_eq!(report.name, "copy_by_mock_bot");
+    assert_eq!(report.uploaded, 2);
+    assert_eq!(report.reused, 50);
+    assert_eq!(server.hits("uploadStickerFile"), 2);
+
+    let set = bot.request_sticker_set(&report.name).await.unwrap();
+    assert_eq!(set.title, "Copy");
+    assert_eq!(set.stickers.len(), 52);
+    assert_eq!(set.stickers[0].emoji.as_deref(), Some("🐱"));
+    assert!(set.stickers[51].is_video);
+    let dir = tempfile::tempdir().unwrap();
+    for idx in [0, 51] {
+        let path = bot.request_file_id(&set.stickers[idx]).await.unwrap();
+        let len = tsticker::download::download_sticker(&bot, &path, &dir.path().join("sticker"))
+            .await
+            .unwrap();
+        assert_eq!(len, format!("AgADSource{}", idx).len() as u64);
+    }
+
+    let err = bot
+        .clone_sticker_set(42, &source, "copy", "Copy")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("already occupied"));
+
+    // Other bad requests are not worked around by uploading again
+    server.fail(
+        "createNewStickerSet",
+        400,
+        "Bad Request: USER_ID_INVALID",
+        None,
+    );
+    let err = bot
+        .clone_sticker_set(42, &source, "other", "Other")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("USER_ID_INVALID"));
+    // and a half built set is deleted
+    server.fail(
+        "addStickerToSet",
+        400,
+        "Bad Request: STICKERS_TOO_MUCH",
+        None,
+    );
+    let err = bot
+        .clone_sticker_set(42, &source, "other", "Other")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("STICKERS_TOO_MUCH"));
+    assert!(bot
+        .request_sticker_set("other_by_mock_bot")
+        .await
+        .unwrap_err()
+        .is_not_found());
+    assert_eq!(server.hits("uploadStickerFile"), 2);
+}